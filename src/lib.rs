@@ -0,0 +1,2437 @@
+//! Fuzzy matching of git branch names, and the git plumbing to find and
+//! check out branches.
+//!
+//! The `git-fuzzy` binary is a thin command line interface over this library.
+
+use colored::{Color, ColoredString, Colorize};
+use globset::{GlobBuilder, GlobMatcher};
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Everything that can go wrong finding or checking out a branch
+#[derive(Debug, thiserror::Error)]
+pub enum GitFuzzyError {
+    /// There's no repository here, or in `dir` when given, like `git -C`
+    #[error("{}", describe_not_a_repo(dir.as_deref()))]
+    NotARepo {
+        /// The directory that was searched, if not the current one
+        dir: Option<PathBuf>,
+    },
+    /// There's no git executable on `PATH`
+    #[error("git executable not found on PATH; please install git")]
+    GitNotFound,
+    /// Git is on `PATH`, but couldn't be run, e.g. for lack of permission
+    #[error("Failed to execute git: {0}")]
+    GitSpawn(#[source] io::Error),
+    /// Git ran for longer than `--timeout` allows, so it was killed
+    #[error("{command} timed out after {}s", timeout.as_secs())]
+    GitTimeout {
+        /// The command line, e.g. `git fetch --prune --all`
+        command: String,
+        /// How long git was given
+        timeout: Duration,
+    },
+    /// Git ran, but failed
+    #[error("{command} failed{}", describe_stderr(stderr))]
+    GitCommand {
+        /// The command line, e.g. `git switch main`
+        command: String,
+        /// What git printed on stderr, if it was captured
+        stderr: String,
+    },
+    /// The pattern matched several candidates and none could be chosen
+    #[error("'{pattern}' is ambiguous, matching {}", matches.join(", "))]
+    Ambiguous {
+        /// The pattern
+        pattern: String,
+        /// The names of all the candidates it matched
+        matches: Vec<String>,
+    },
+    /// Nothing matched
+    #[error("{0}")]
+    NotFound(String),
+    /// A `--regex` or `--glob` pattern that doesn't parse
+    #[error("{0}")]
+    InvalidPattern(String),
+    /// Anything else, described for the user
+    #[error("{0}")]
+    Other(String),
+}
+
+/// Describe why git couldn't be started, telling a missing git apart, since
+/// that's what new users run into
+fn spawn_error(error: io::Error) -> GitFuzzyError {
+    match error.kind() {
+        io::ErrorKind::NotFound => GitFuzzyError::GitNotFound,
+        _ => GitFuzzyError::GitSpawn(error),
+    }
+}
+
+/// Say which directory isn't a repository
+fn describe_not_a_repo(dir: Option<&Path>) -> String {
+    match dir {
+        Some(dir) => format!("'{}' is not a git repository", dir.display()),
+        None => "Not in a git repository".to_string(),
+    }
+}
+
+/// Git's stderr as a suffix for an error message, if there was any
+fn describe_stderr(stderr: &str) -> String {
+    match stderr.trim() {
+        "" => String::new(),
+        stderr => format!(": {}", stderr),
+    }
+}
+
+/// How the pattern is matched against branch names
+pub enum Matcher {
+    /// The built-in exact, prefix, substring and fuzzy passes
+    Fuzzy,
+    /// Only exact matches
+    Exact,
+    /// A regular expression, from `--regex`
+    Regex(Regex),
+    /// A shell-style glob, from `--glob`
+    Glob(GlobMatcher),
+    /// A literal that must start and/or end the name, from a pattern like
+    /// `^feat` or `rc$`
+    Anchored {
+        literal: String,
+        start: bool,
+        end: bool,
+    },
+}
+
+impl Matcher {
+    /// Build a matcher that treats the pattern as a regular expression
+    pub fn regex(pattern: &str, ignore_case: bool) -> Result<Self, GitFuzzyError> {
+        RegexBuilder::new(pattern)
+            .case_insensitive(ignore_case)
+            .build()
+            .map(Matcher::Regex)
+            .map_err(|e| {
+                GitFuzzyError::InvalidPattern(format!("Invalid regex '{}': {}", pattern, e))
+            })
+    }
+
+    /// Build a matcher that treats the pattern as a shell-style glob
+    pub fn glob(pattern: &str, ignore_case: bool) -> Result<Self, GitFuzzyError> {
+        GlobBuilder::new(pattern)
+            .case_insensitive(ignore_case)
+            .build()
+            .map(|glob| Matcher::Glob(glob.compile_matcher()))
+            .map_err(|e| {
+                GitFuzzyError::InvalidPattern(format!("Invalid glob '{}': {}", pattern, e))
+            })
+    }
+
+    /// Build a matcher for a pattern anchored with a leading `^` and/or a
+    /// trailing `$`, or `None` if it has neither
+    pub fn anchored(pattern: &str) -> Option<Self> {
+        let (start, rest) = match pattern.strip_prefix('^') {
+            Some(rest) => (true, rest),
+            None => (false, pattern),
+        };
+        let (end, literal) = match rest.strip_suffix('$') {
+            Some(literal) => (true, literal),
+            None => (false, rest),
+        };
+        if !(start || end) || literal.is_empty() {
+            return None;
+        }
+        Some(Matcher::Anchored {
+            literal: literal.to_string(),
+            start,
+            end,
+        })
+    }
+
+    /// Find the branches matching the pattern, with their scores, best first
+    pub fn find_matches(
+        &self,
+        branches: &[Branch],
+        needle: &str,
+        ignore_case: bool,
+        threshold: Option<usize>,
+    ) -> Vec<(Branch, u32)> {
+        match self {
+            // Regex and glob matches are all equally good, so only a unique
+            // match is checked out
+            Matcher::Regex(regex) => match_branch_regex(branches, regex)
+                .into_iter()
+                .map(|b| (b, 0))
+                .collect(),
+            Matcher::Glob(glob) => match_branch_glob(branches, glob)
+                .into_iter()
+                .map(|b| (b, 0))
+                .collect(),
+            Matcher::Fuzzy => find_matches(branches, needle, ignore_case, threshold),
+            Matcher::Exact => match_branch_exactly(branches, needle, ignore_case)
+                .into_iter()
+                .map(|b| (b, EXACT_SCORE))
+                .collect(),
+            // Anchored matches are equally good too, except that the whole
+            // name beats a longer one, so `^develop` prefers `develop`
+            Matcher::Anchored {
+                literal,
+                start,
+                end,
+            } => {
+                let literal = fold_case(literal, ignore_case);
+                let mut ranked: Vec<(Branch, u32)> = branches
+                    .iter()
+                    .filter_map(|b| {
+                        let name = fold_case(&b.name, ignore_case);
+                        // Both anchors pin the literal to the whole name,
+                        // so `^feat$` doesn't match `feat-feat`
+                        let matched = match (start, end) {
+                            (true, true) => name == literal,
+                            (true, false) => name.starts_with(&literal),
+                            _ => name.ends_with(&literal),
+                        };
+                        let score = if name == literal { EXACT_SCORE } else { 0 };
+                        matched.then(|| (b.clone(), score))
+                    })
+                    .collect();
+                ranked.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+                ranked
+            }
+        }
+    }
+
+    /// Find matches like `find_matches`, but only against part of each
+    /// branch's name, like its basename, so that namespaces like
+    /// `team/project/` never match. Branches whose parts are the same match
+    /// together.
+    pub fn find_matches_by<F>(
+        &self,
+        branches: &[Branch],
+        needle: &str,
+        ignore_case: bool,
+        threshold: Option<usize>,
+        key: F,
+    ) -> Vec<(Branch, u32)>
+    where
+        F: Fn(&Branch) -> &str,
+    {
+        let mut keys: Vec<Branch> = Vec::new();
+        let mut positions: HashMap<&str, usize> = HashMap::new();
+        for branch in branches {
+            let name = key(branch);
+            match positions.get(name) {
+                // Keep the newest date, which breaks ties between matches
+                Some(&i) => {
+                    keys[i].committer_date = keys[i].committer_date.max(branch.committer_date)
+                }
+                None => {
+                    positions.insert(name, keys.len());
+                    keys.push(Branch::new(
+                        name.to_string(),
+                        branch.kind,
+                        branch.committer_date,
+                    ));
+                }
+            }
+        }
+
+        self.find_matches(&keys, needle, ignore_case, threshold)
+            .into_iter()
+            .flat_map(|(matched, score)| {
+                branches
+                    .iter()
+                    .filter(|b| key(b) == matched.name)
+                    .map(move |b| (b.clone(), score))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Whether a match with this score is a sure one, rather than a guess by
+    /// the fuzzy passes for initials, subsequences and typos, which a better
+    /// match elsewhere should beat
+    pub fn is_confident(&self, score: u32) -> bool {
+        match self {
+            Matcher::Fuzzy => score >= SUBSTRING_SCORE,
+            Matcher::Exact | Matcher::Regex(_) | Matcher::Glob(_) | Matcher::Anchored { .. } => {
+                true
+            }
+        }
+    }
+
+    /// Highlight the part of a branch name matched by the pattern
+    pub fn highlight(&self, branch_name: &str, needle: &str, ignore_case: bool) -> String {
+        match self {
+            Matcher::Regex(regex) => highlight_regex(branch_name, regex),
+            Matcher::Glob(glob) => highlight_glob(branch_name, glob, ignore_case),
+            Matcher::Fuzzy => highlight_branch(branch_name, needle, ignore_case),
+            Matcher::Exact => highlight_match(branch_name, needle, ignore_case),
+            Matcher::Anchored { literal, start, .. } => {
+                highlight_anchored(branch_name, literal, *start, ignore_case)
+            }
+        }
+    }
+}
+
+/// What kind of ref a checkout candidate is, ordered as they are listed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RefKind {
+    /// A local branch under `refs/heads/`
+    Local,
+    /// A remote-tracking branch under `refs/remotes/`
+    Remote,
+    /// A tag under `refs/tags/`
+    Tag,
+    /// A commit given by its hash
+    Commit,
+}
+
+impl RefKind {
+    /// The lowercase name of the kind, as used by `--json` and `--porcelain`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RefKind::Local => "local",
+            RefKind::Remote => "remote",
+            RefKind::Tag => "tag",
+            RefKind::Commit => "commit",
+        }
+    }
+}
+
+/// A checkout candidate: a local or remote branch, a tag, or a commit
+#[derive(Debug, Clone)]
+pub struct Branch {
+    /// The short ref name, e.g. `main` or `origin/main`
+    pub name: String,
+    /// Whether this is a local or remote branch, a tag or a commit
+    pub kind: RefKind,
+    /// Unix timestamp of the ref's last commit
+    pub committer_date: i64,
+    /// How a local branch compares to its upstream, as git summarises it,
+    /// e.g. `[ahead 1, behind 2]` or `[gone]`
+    pub upstream_track: Option<String>,
+    /// A local branch's `branch.<name>.description`, when descriptions have
+    /// been looked up
+    pub description: Option<String>,
+    /// The remote of a remote branch, e.g. `origin`, which may itself
+    /// contain slashes
+    pub remote: Option<String>,
+}
+
+impl Branch {
+    /// Create a checkout candidate
+    pub fn new(name: String, kind: RefKind, committer_date: i64) -> Self {
+        Branch {
+            name,
+            kind,
+            committer_date,
+            upstream_track: None,
+            description: None,
+            remote: None,
+        }
+    }
+
+    /// Whether this is a local branch
+    pub fn is_local(&self) -> bool {
+        self.kind == RefKind::Local
+    }
+
+    /// Whether this is a remote-tracking branch
+    pub fn is_remote(&self) -> bool {
+        self.kind == RefKind::Remote
+    }
+
+    /// Add a marker to an already highlighted name for refs that aren't
+    /// branches, so tags stand out in listings, and the first line of the
+    /// branch's description if it has one
+    pub fn label(&self, highlighted: String) -> String {
+        let label = match self.kind {
+            RefKind::Tag => format!("{} {}", highlighted, "(tag)".dimmed()),
+            RefKind::Commit => format!("{} {}", highlighted, "(commit)".dimmed()),
+            RefKind::Local | RefKind::Remote => highlighted,
+        };
+        match &self.description {
+            Some(description) => {
+                let summary = description.lines().next().unwrap_or_default();
+                let suffix = format!("(description: {})", summary);
+                format!("{} {}", label, suffix.dimmed())
+            }
+            None => label,
+        }
+    }
+
+    /// Split a remote branch into its remote and branch name
+    fn split_remote(&self) -> Option<(&str, &str)> {
+        let remote = self.remote.as_deref().filter(|_| self.is_remote())?;
+        let name = self.name.strip_prefix(remote)?.strip_prefix('/')?;
+        Some((remote, name))
+    }
+
+    /// The name without its remote, e.g. `feature-x` for `origin/feature-x`
+    pub fn short_name(&self) -> &str {
+        self.split_remote().map_or(&self.name, |(_, name)| name)
+    }
+
+    /// The plain form of `display_label`, without colors or upstream status,
+    /// e.g. `feature-x (origin)` or `v1.0 (tag)`
+    pub fn display_name(&self) -> String {
+        match (self.split_remote(), self.kind) {
+            (Some((remote, name)), _) => format!("{} ({})", name, remote),
+            (None, RefKind::Tag) => format!("{} (tag)", self.name),
+            (None, RefKind::Commit) => format!("{} (commit)", self.name),
+            (None, _) => self.name.clone(),
+        }
+    }
+
+    /// The label to show when listing candidates, with `highlighted` as the
+    /// already highlighted `short_name` and the remote of a remote branch
+    /// after it, e.g. `feature-x (origin)`. Markers are dimmed, and a local
+    /// branch shows how far it is ahead of or behind its upstream.
+    pub fn display_label(&self, highlighted: String) -> String {
+        let label = match self.split_remote() {
+            Some((remote, _)) => format!("{} {}", highlighted, format!("({})", remote).dimmed()),
+            None => self.label(highlighted),
+        };
+        match &self.upstream_track {
+            Some(track) => format!("{} {}", label, track.yellow()),
+            None => label,
+        }
+    }
+}
+
+/// Find the git directory of the current repository. Asking git handles
+/// worktrees, submodules, bare repositories and `GIT_DIR` just like git does.
+pub fn find_git_directory(git: &dyn GitRunner) -> Result<PathBuf, GitFuzzyError> {
+    git.run(&["rev-parse", "--absolute-git-dir"])
+        .map(|s| PathBuf::from(s.trim()))
+        .map_err(|e| match e {
+            GitFuzzyError::GitCommand { .. } => GitFuzzyError::NotARepo { dir: None },
+            e => e,
+        })
+}
+
+/// Check whether the repository is bare, i.e. has no working tree to check
+/// anything out into
+pub fn is_bare_repository(git: &dyn GitRunner) -> bool {
+    git.run(&["rev-parse", "--is-bare-repository"])
+        .is_ok_and(|s| s.trim() == "true")
+}
+
+/// Check whether this is a linked worktree, added with `git worktree add`,
+/// rather than the main one
+pub fn is_linked_worktree(git: &dyn GitRunner) -> bool {
+    let dirs = git
+        .run(&[
+            "rev-parse",
+            "--path-format=absolute",
+            "--git-dir",
+            "--git-common-dir",
+        ])
+        .unwrap_or_default();
+    let mut dirs = dirs.lines();
+    match (dirs.next(), dirs.next()) {
+        (Some(git_dir), Some(common_dir)) => git_dir != common_dir,
+        _ => false,
+    }
+}
+
+/// Whether `--verbose` tracing is enabled
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+/// Enable or disable `--verbose` tracing
+pub fn set_verbose(enabled: bool) {
+    VERBOSE.store(enabled, Ordering::Relaxed);
+}
+
+/// Print a message to stderr if `--verbose` is enabled
+pub fn log_verbose(message: &str) {
+    if VERBOSE.load(Ordering::Relaxed) {
+        eprintln!("{} {}", "[git-fuzzy]".dimmed(), message);
+    }
+}
+
+/// The color that matched parts of branch names are highlighted in
+static HIGHLIGHT_COLOR: RwLock<Color> = RwLock::new(Color::Green);
+
+/// Set the color that matches are highlighted in, green by default
+pub fn set_highlight_color(color: Color) {
+    *HIGHLIGHT_COLOR.write().unwrap_or_else(|e| e.into_inner()) = color;
+}
+
+/// Color part of a branch name as matched
+fn paint_match(s: &str) -> ColoredString {
+    let color = *HIGHLIGHT_COLOR.read().unwrap_or_else(|e| e.into_inner());
+    s.color(color).bold()
+}
+
+/// Runs git commands. Everything that talks to git goes through this, so
+/// that tests can supply canned output instead of needing a real repository.
+pub trait GitRunner {
+    /// Run git with the given arguments and return its stdout
+    fn run(&self, args: &[&str]) -> Result<String, GitFuzzyError>;
+
+    /// Run git with the given arguments, letting it print to the terminal
+    fn run_interactive(&self, args: &[&str]) -> Result<(), GitFuzzyError> {
+        self.run(args).map(|_| ())
+    }
+
+    /// List the refs under `prefixes`, one per line in the format of
+    /// [`REF_FORMAT`]
+    fn list_refs(&self, prefixes: &[&str]) -> Result<String, GitFuzzyError> {
+        let format_arg = format!("--format={}", REF_FORMAT);
+        let mut args = vec!["for-each-ref", format_arg.as_str()];
+        args.extend(prefixes);
+        self.run(&args)
+    }
+}
+
+/// The tab separated `for-each-ref` fields that refs are listed with: full
+/// name, short name, committer date and upstream tracking status.
+///
+/// Annotated tags have no committer date of their own, only the commit
+/// they point to (`*`), and at most one of the two is ever set.
+pub const REF_FORMAT: &str = "%(refname)%09%(refname:short)%09%(committerdate:unix)%(*committerdate:unix)%09%(upstream:track)";
+
+/// The real git, run as a subprocess
+#[derive(Default)]
+pub struct SystemGit {
+    /// Run git as if started in this directory, like `git -C`
+    pub dir: Option<PathBuf>,
+    /// Kill git and fail if it captured output for longer than this.
+    /// Interactive commands, like checkouts, can take as long as they need.
+    pub timeout: Option<Duration>,
+}
+
+impl SystemGit {
+    /// Build a git command, logging it for `--verbose`
+    fn command(&self, args: &[&str]) -> Command {
+        let mut cmd = Command::new("git");
+        if let Some(dir) = &self.dir {
+            cmd.arg("-C").arg(dir);
+        }
+        cmd.args(args);
+
+        let all_args: Vec<String> = cmd
+            .get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect();
+        log_verbose(&format!("Running: git {}", all_args.join(" ")));
+        cmd
+    }
+}
+
+impl GitRunner for SystemGit {
+    fn run(&self, args: &[&str]) -> Result<String, GitFuzzyError> {
+        let mut command = self.command(args);
+        let output = match self.timeout {
+            Some(timeout) => {
+                let child = command
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()
+                    .map_err(spawn_error)?;
+                wait_with_timeout(child, timeout)
+                    .map_err(spawn_error)?
+                    .ok_or_else(|| GitFuzzyError::GitTimeout {
+                        command: format!("git {}", args.join(" ")),
+                        timeout,
+                    })?
+            }
+            None => command.output().map_err(spawn_error)?,
+        };
+
+        if !output.status.success() {
+            return Err(GitFuzzyError::GitCommand {
+                command: format!("git {}", args.join(" ")),
+                stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            });
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    fn run_interactive(&self, args: &[&str]) -> Result<(), GitFuzzyError> {
+        let status = self.command(args).status().map_err(spawn_error)?;
+
+        // Git has already printed why it failed
+        if !status.success() {
+            return Err(GitFuzzyError::GitCommand {
+                command: format!("git {}", args.join(" ")),
+                stderr: String::new(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Collect a child's output like `Child::wait_with_output`, unless it takes
+/// longer than `timeout`, in which case it's killed and `None` returned
+fn wait_with_timeout(mut child: Child, timeout: Duration) -> io::Result<Option<Output>> {
+    // Read the pipes as the child writes to them, or it could block on a
+    // full pipe and never exit
+    let read_all = |mut pipe: Box<dyn Read + Send>| {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            pipe.read_to_end(&mut buf).map(|_| buf)
+        })
+    };
+    let stdout = read_all(Box::new(child.stdout.take().expect("stdout is piped")));
+    let stderr = read_all(Box::new(child.stderr.take().expect("stderr is piped")));
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            // Anything git started may still hold the pipes open, so leave
+            // the readers behind rather than waiting for them
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    let join = |reader: thread::JoinHandle<io::Result<Vec<u8>>>| {
+        reader.join().expect("pipe reader doesn't panic")
+    };
+    Ok(Some(Output {
+        status,
+        stdout: join(stdout)?,
+        stderr: join(stderr)?,
+    }))
+}
+
+/// Reads refs in-process with gitoxide instead of spawning
+/// `git for-each-ref`, and runs the real git for everything else
+#[cfg(feature = "gix")]
+pub struct GixGit {
+    repo: gix::Repository,
+    system: SystemGit,
+}
+
+#[cfg(feature = "gix")]
+impl GixGit {
+    /// Open the repository that `system` runs git in
+    pub fn open(system: SystemGit) -> Result<Self, GitFuzzyError> {
+        let repo =
+            gix::discover(system.dir.as_deref().unwrap_or(Path::new("."))).map_err(|_| {
+                GitFuzzyError::NotARepo {
+                    dir: system.dir.clone(),
+                }
+            })?;
+        Ok(GixGit { repo, system })
+    }
+}
+
+#[cfg(feature = "gix")]
+impl GitRunner for GixGit {
+    fn run(&self, args: &[&str]) -> Result<String, GitFuzzyError> {
+        self.system.run(args)
+    }
+
+    fn run_interactive(&self, args: &[&str]) -> Result<(), GitFuzzyError> {
+        self.system.run_interactive(args)
+    }
+
+    /// Lists the same fields as [`REF_FORMAT`], except the upstream tracking
+    /// status, which is always empty
+    fn list_refs(&self, prefixes: &[&str]) -> Result<String, GitFuzzyError> {
+        log_verbose(&format!(
+            "Reading refs with gitoxide: {}",
+            prefixes.join(" ")
+        ));
+        let gix_error =
+            |e: &dyn std::fmt::Display| GitFuzzyError::Other(format!("Failed to read refs: {}", e));
+
+        let platform = self.repo.references().map_err(|e| gix_error(&e))?;
+        let mut lines = String::new();
+        for prefix in prefixes {
+            for reference in platform.prefixed(*prefix).map_err(|e| gix_error(&e))? {
+                let mut reference = reference.map_err(|e| gix_error(&e))?;
+                let full_name = reference.name().as_bstr().to_string();
+                let name = reference.name().shorten().to_string();
+                // Peeling goes through annotated tags to the commit they
+                // point to, like `*committerdate`
+                let date = reference
+                    .peel_to_commit()
+                    .ok()
+                    .and_then(|commit| commit.time().ok())
+                    .map_or(0, |time| time.seconds);
+                lines.push_str(&format!("{}\t{}\t{}\t\n", full_name, name, date));
+            }
+        }
+        Ok(lines)
+    }
+}
+
+/// Get all git remotes
+pub fn get_git_remotes(git: &dyn GitRunner) -> Vec<String> {
+    git.run(&["remote"])
+        .unwrap_or_default()
+        .lines()
+        .map(|s| s.to_string())
+        .collect()
+}
+
+/// Fetch from one remote, or from all of them, pruning deleted branches
+pub fn fetch_remotes(git: &dyn GitRunner, remote: Option<&str>) -> Result<(), GitFuzzyError> {
+    let mut args = vec!["fetch", "--prune"];
+    match remote {
+        Some(remote) => args.push(remote),
+        None => args.push("--all"),
+    }
+
+    git.run(&args).map(|_| ())
+}
+
+/// Get the remote to prefer when a branch exists on several: git's own
+/// `checkout.defaultRemote`, else `origin` if there is one, else the first
+/// remote
+pub fn get_default_remote(git: &dyn GitRunner) -> Option<String> {
+    let configured = git
+        .run(&["config", "--get", "checkout.defaultRemote"])
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty());
+    if configured.is_some() {
+        return configured;
+    }
+
+    let remotes = get_git_remotes(git);
+    if remotes.iter().any(|r| r == "origin") {
+        return Some("origin".to_string());
+    }
+    remotes.into_iter().next()
+}
+
+/// How recently each branch was checked out, from the HEAD reflog: 0 for the
+/// branch checked out last, 1 for the one before it and so on. Branches that
+/// haven't been checked out since the reflog began are missing.
+pub fn branch_recency(git: &dyn GitRunner) -> HashMap<String, usize> {
+    let mut recency = HashMap::new();
+    let reflog = git
+        .run(&["reflog", "show", "--format=%gs", "HEAD"])
+        .unwrap_or_default();
+    for line in reflog.lines() {
+        // Both checkout and switch log "checkout: moving from <old> to <new>"
+        let Some((_, target)) = line
+            .strip_prefix("checkout: moving from ")
+            .and_then(|moves| moves.rsplit_once(" to "))
+        else {
+            continue;
+        };
+        let next = recency.len();
+        recency.entry(target.to_string()).or_insert(next);
+    }
+    recency
+}
+
+/// Get the current branch name
+pub fn get_current_branch(git: &dyn GitRunner) -> Option<String> {
+    git.run(&["branch", "--show-current"])
+        .ok()
+        .map(|s| s.trim().to_string())
+}
+
+/// Check whether HEAD is detached, i.e. isn't a symbolic ref to a branch
+pub fn is_detached_head(git: &dyn GitRunner) -> bool {
+    git.run(&["symbolic-ref", "-q", "HEAD"]).is_err()
+}
+
+/// Get the abbreviated hash of the commit HEAD is at
+pub fn get_head_commit(git: &dyn GitRunner) -> Option<String> {
+    git.run(&["rev-parse", "--short", "HEAD"])
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+/// Get all git refs (branches)
+pub fn get_git_refs(git: &dyn GitRunner, prefix: &str) -> Result<Vec<String>, GitFuzzyError> {
+    let format_arg = "--format=%(refname:short)";
+    let output = git.run(&["for-each-ref", format_arg, prefix])?;
+    Ok(output.lines().map(|s| s.to_string()).collect())
+}
+
+/// Get the branches and tags under the given ref prefixes with a single
+/// `git for-each-ref`, telling them apart by their full ref name, e.g.
+/// `refs/heads/` and `refs/tags/` for local branches and tags. Failing to
+/// list them, e.g. because git timed out, is an error rather than no refs.
+pub fn get_refs(git: &dyn GitRunner, prefixes: &[&str]) -> Result<Vec<Branch>, GitFuzzyError> {
+    let mut branches: Vec<Branch> = git
+        .list_refs(prefixes)?
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, '\t');
+            let full_name = fields.next()?;
+            let name = fields.next()?;
+            let date = fields.next().and_then(|d| d.parse().ok()).unwrap_or(0);
+            // Empty when there's no upstream or the branch is up to date
+            let track = fields.next().filter(|t| !t.is_empty());
+
+            // A remote's HEAD is a symbolic ref to its default branch, which
+            // is already a candidate under its own name
+            if full_name.starts_with("refs/remotes/") && full_name.ends_with("/HEAD") {
+                return None;
+            }
+
+            let kind = if full_name.starts_with("refs/heads/") {
+                RefKind::Local
+            } else if full_name.starts_with("refs/remotes/") {
+                RefKind::Remote
+            } else if full_name.starts_with("refs/tags/") {
+                RefKind::Tag
+            } else {
+                return None;
+            };
+            let mut branch = Branch::new(name.to_string(), kind, date);
+            branch.upstream_track = track.map(|t| t.to_string());
+            Some(branch)
+        })
+        .collect();
+
+    // Either part of a remote branch's name may contain slashes, so its
+    // remote is looked up among the known ones, for a remote that isn't
+    // configured any more going by the first slash
+    if branches.iter().any(Branch::is_remote) {
+        let remotes = get_git_remotes(git);
+        for branch in branches.iter_mut().filter(|b| b.is_remote()) {
+            branch.remote = split_remote_branch(&branch.name, &remotes)
+                .or_else(|| branch.name.split_once('/'))
+                .map(|(remote, _)| remote.to_string());
+        }
+    }
+    Ok(branches)
+}
+
+/// Get the descriptions of local branches, as set by `git branch
+/// --edit-description`, keyed by branch name
+pub fn get_branch_descriptions(git: &dyn GitRunner) -> HashMap<String, String> {
+    // With -z each entry is the key, a newline and then the value, so
+    // multi-line descriptions survive
+    git.run(&["config", "-z", "--get-regexp", r"^branch\..*\.description$"])
+        .unwrap_or_default()
+        .split('\0')
+        .filter_map(|entry| {
+            let (key, value) = entry.split_once('\n')?;
+            let name = key.strip_prefix("branch.")?.strip_suffix(".description")?;
+            let value = value.trim();
+            (!value.is_empty()).then(|| (name.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Get local branches only, without querying any remotes
+pub fn get_local_branches(git: &dyn GitRunner) -> Result<Vec<Branch>, GitFuzzyError> {
+    get_refs(git, &["refs/heads/"])
+}
+
+/// Get remote-tracking branches only
+pub fn get_remote_branches(git: &dyn GitRunner) -> Result<Vec<Branch>, GitFuzzyError> {
+    get_refs(git, &["refs/remotes/"])
+}
+
+/// Get all branches (local and remote)
+pub fn get_all_branches(git: &dyn GitRunner) -> Result<Vec<Branch>, GitFuzzyError> {
+    get_refs(git, &["refs/heads/", "refs/remotes/"])
+}
+
+/// The part of a branch name after its last slash, e.g. `feature-login` for
+/// `team/project/feature-login` or `origin/team/project/feature-login`
+pub fn branch_basename(name: &str) -> &str {
+    name.rsplit('/').next().unwrap_or(name)
+}
+
+/// The part of a branch name after the first of `prefixes` it starts with,
+/// e.g. `login` for `feature/login` or `origin/feature/login`, or else the
+/// whole name
+pub fn strip_branch_prefix<'a>(branch: &'a Branch, prefixes: &[String]) -> &'a str {
+    let name = branch.short_name();
+    prefixes
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix.as_str()))
+        .filter(|rest| !rest.is_empty())
+        .unwrap_or(&branch.name)
+}
+
+/// Split a remote branch name like `origin/feature/foo` into its remote and
+/// branch name, using the known remotes so that slashes in either part are
+/// handled correctly. The longest matching remote wins.
+pub fn split_remote_branch<'a>(name: &'a str, remotes: &[String]) -> Option<(&'a str, &'a str)> {
+    remotes
+        .iter()
+        .filter(|remote| {
+            name.len() > remote.len()
+                && name.starts_with(remote.as_str())
+                && name.as_bytes()[remote.len()] == b'/'
+        })
+        .max_by_key(|remote| remote.len())
+        .map(|remote| (&name[..remote.len()], &name[remote.len() + 1..]))
+}
+
+/// Check whether `remote` is the remote-tracking counterpart of the local
+/// branch `local`, e.g. `origin/main` for `main`
+fn is_remote_counterpart(local: &Branch, remote: &Branch, remotes: &[String]) -> bool {
+    local.is_local()
+        && remote.is_remote()
+        && split_remote_branch(&remote.name, remotes)
+            .is_some_and(|(_, branch_name)| branch_name == local.name)
+}
+
+/// Drop remote branches whose local counterpart is also among the matches,
+/// so that matching both `main` and `origin/main` isn't ambiguous
+pub fn prefer_local_branches(
+    git: &dyn GitRunner,
+    ranked: Vec<(Branch, u32)>,
+) -> Vec<(Branch, u32)> {
+    let has_remote = ranked.iter().any(|(b, _)| b.is_remote());
+    let has_local = ranked.iter().any(|(b, _)| b.is_local());
+    if !(has_remote && has_local) {
+        return ranked;
+    }
+
+    let remotes = get_git_remotes(git);
+    let locals: Vec<Branch> = ranked
+        .iter()
+        .filter(|(b, _)| b.is_local())
+        .map(|(b, _)| b.clone())
+        .collect();
+
+    ranked
+        .into_iter()
+        .filter(|(b, _)| {
+            !locals
+                .iter()
+                .any(|local| is_remote_counterpart(local, b, &remotes))
+        })
+        .collect()
+}
+
+/// The one match to go with among `ranked`, best first: the only match, or
+/// the best one if it leads the runner-up by at least `min_margin`. `None`
+/// if nothing matched or the best matches are too close to call.
+pub fn pick_unique(ranked: &[(Branch, u32)], min_margin: u32) -> Option<&Branch> {
+    match ranked {
+        [(branch, _)] => Some(branch),
+        [(branch, best_score), (_, second_score), ..]
+            if best_score.saturating_sub(*second_score) >= min_margin =>
+        {
+            Some(branch)
+        }
+        _ => None,
+    }
+}
+
+/// Keep only the branches whose name contains every one of the `required`
+/// substrings. Candidates are narrowed down like this before matching, so
+/// that a pass never stops at branches that would be dropped afterwards.
+pub fn require_substrings(
+    branches: &[Branch],
+    required: &[String],
+    ignore_case: bool,
+) -> Vec<Branch> {
+    let required: Vec<String> = required.iter().map(|r| fold_case(r, ignore_case)).collect();
+    branches
+        .iter()
+        .filter(|b| {
+            let name = fold_case(&b.name, ignore_case);
+            required.iter().all(|r| name.contains(r.as_str()))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Drop the branches whose name contains any of the `excluded` substrings,
+/// before matching like `require_substrings`
+pub fn exclude_substrings(
+    branches: &[Branch],
+    excluded: &[String],
+    ignore_case: bool,
+) -> Vec<Branch> {
+    let excluded: Vec<String> = excluded.iter().map(|e| fold_case(e, ignore_case)).collect();
+    branches
+        .iter()
+        .filter(|b| {
+            let name = fold_case(&b.name, ignore_case);
+            !excluded.iter().any(|e| name.contains(e.as_str()))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Get tracking branches (local branches + remote branches without local
+/// counterparts). When several remotes have a branch of the same name, only
+/// the one on the earliest of `preferred_remotes` is kept; if none of them is
+/// preferred, they are all kept.
+pub fn get_tracking_branches(
+    git: &dyn GitRunner,
+    preferred_remotes: &[String],
+) -> Result<Vec<Branch>, GitFuzzyError> {
+    Ok(select_tracking_branches(
+        git,
+        get_all_branches(git)?,
+        preferred_remotes,
+    ))
+}
+
+/// Narrow refs collected with `get_refs` down to tracking branches, like
+/// `get_tracking_branches`. Anything that isn't a remote branch, like a tag,
+/// is kept.
+pub fn select_tracking_branches(
+    git: &dyn GitRunner,
+    branches: Vec<Branch>,
+    preferred_remotes: &[String],
+) -> Vec<Branch> {
+    let remotes = get_git_remotes(git);
+    let local_names: HashSet<&str> = branches
+        .iter()
+        .filter(|b| b.is_local())
+        .map(|b| b.name.as_str())
+        .collect();
+
+    // Either part of "remote/branch-name" may contain slashes, so strip the
+    // known remote rather than splitting
+    let remote_parts: Vec<Option<(&str, &str)>> = branches
+        .iter()
+        .map(|b| {
+            b.is_remote()
+                .then(|| split_remote_branch(&b.name, &remotes).or_else(|| b.split_remote()))
+                .flatten()
+        })
+        .collect();
+
+    // Position in the preference order, earlier is better, and the best
+    // position among the remotes that have each branch
+    let rank = |remote: &str| preferred_remotes.iter().position(|r| r == remote);
+    let mut best_ranks: HashMap<&str, usize> = HashMap::new();
+    for &(remote, name) in remote_parts.iter().flatten() {
+        if let Some(rank) = rank(remote) {
+            let best = best_ranks.entry(name).or_insert(rank);
+            *best = (*best).min(rank);
+        }
+    }
+
+    // A remote branch is only offered without a local branch of the same
+    // name, and then only from the most preferred remote that has it
+    let keep: Vec<bool> = branches
+        .iter()
+        .zip(&remote_parts)
+        .map(|(branch, parts)| match parts {
+            _ if !branch.is_remote() => true,
+            None => false,
+            Some((remote, name)) => {
+                !local_names.contains(name)
+                    && match best_ranks.get(name) {
+                        Some(&best) => rank(remote) == Some(best),
+                        None => true,
+                    }
+            }
+        })
+        .collect();
+
+    branches
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(branch, keep)| keep.then_some(branch))
+        .collect()
+}
+
+/// Decide whether matching should ignore case, using smartcase: a pattern
+/// containing an uppercase letter is always matched case-sensitively
+pub fn use_ignore_case(needle: &str, ignore_case: bool) -> bool {
+    ignore_case && !needle.chars().any(char::is_uppercase)
+}
+
+/// Lowercase a string for comparison if matching ignores case
+fn fold_case(s: &str, ignore_case: bool) -> String {
+    if ignore_case {
+        s.to_lowercase()
+    } else {
+        s.to_string()
+    }
+}
+
+/// Fold a string like `fold_case`, also returning, for each byte of the
+/// folded string and one past its end, the byte offset in the original of
+/// the character it came from. Unicode lowercasing can change the length of
+/// a string, so positions found in the folded string need translating.
+fn fold_case_with_offsets(s: &str, ignore_case: bool) -> (String, Vec<usize>) {
+    let mut folded = String::new();
+    let mut offsets = Vec::new();
+    for (i, c) in s.char_indices() {
+        let start = folded.len();
+        if ignore_case {
+            folded.extend(c.to_lowercase());
+        } else {
+            folded.push(c);
+        }
+        offsets.resize(offsets.len() + folded.len() - start, i);
+    }
+    offsets.push(s.len());
+    (folded, offsets)
+}
+
+/// Match branches exactly by name
+pub fn match_branch_exactly(branches: &[Branch], needle: &str, ignore_case: bool) -> Vec<Branch> {
+    let needle = fold_case(needle, ignore_case);
+    branches
+        .iter()
+        .filter(|b| fold_case(&b.name, ignore_case) == needle)
+        .cloned()
+        .collect()
+}
+
+/// Match branches whose name starts with the needle
+pub fn match_branch_prefix(branches: &[Branch], needle: &str, ignore_case: bool) -> Vec<Branch> {
+    let needle = fold_case(needle, ignore_case);
+    branches
+        .iter()
+        .filter(|b| fold_case(&b.name, ignore_case).starts_with(&needle))
+        .cloned()
+        .collect()
+}
+
+/// Match branches by substring
+pub fn match_branch_substring(branches: &[Branch], needle: &str, ignore_case: bool) -> Vec<Branch> {
+    let needle = fold_case(needle, ignore_case);
+    branches
+        .iter()
+        .filter(|b| fold_case(&b.name, ignore_case).contains(&needle))
+        .cloned()
+        .collect()
+}
+
+/// Split a branch name into words on `-`, `_` and `/`, returning each
+/// non-empty word with its byte offset in the name
+fn split_words(name: &str) -> Vec<(usize, &str)> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    for (i, c) in name.char_indices() {
+        if matches!(c, '-' | '_' | '/') {
+            if i > start {
+                words.push((start, &name[start..i]));
+            }
+            start = i + c.len_utf8();
+        }
+    }
+    if start < name.len() {
+        words.push((start, &name[start..]));
+    }
+    words
+}
+
+/// Find where the needle occurs in the initials of a branch name's words,
+/// returning the byte offsets of the matched initial characters
+fn find_initials(name: &str, needle: &str) -> Option<Vec<usize>> {
+    if needle.is_empty() {
+        return None;
+    }
+
+    let words = split_words(name);
+    let initials: Vec<char> = words
+        .iter()
+        .filter_map(|(_, word)| word.chars().next())
+        .collect();
+    let needle: Vec<char> = needle.chars().collect();
+
+    let start = initials
+        .windows(needle.len())
+        .position(|window| window == needle.as_slice())?;
+    Some(
+        words[start..start + needle.len()]
+            .iter()
+            .map(|(offset, _)| *offset)
+            .collect(),
+    )
+}
+
+/// Match branches whose word initials contain the needle (e.g. `upr` matches
+/// `feature/user-profile-redesign`)
+pub fn match_branch_initials(branches: &[Branch], needle: &str, ignore_case: bool) -> Vec<Branch> {
+    let needle = fold_case(needle, ignore_case);
+    branches
+        .iter()
+        .filter(|b| find_initials(&fold_case(&b.name, ignore_case), &needle).is_some())
+        .cloned()
+        .collect()
+}
+
+/// Check whether the characters of `needle` appear in order in `haystack`
+fn is_subsequence(haystack: &str, needle: &str) -> bool {
+    let mut needle_chars = needle.chars().peekable();
+    for c in haystack.chars() {
+        match needle_chars.peek() {
+            Some(&n) if n == c => {
+                needle_chars.next();
+            }
+            Some(_) => {}
+            None => break,
+        }
+    }
+    needle_chars.peek().is_none()
+}
+
+/// Match branches whose name contains the characters of the needle in order
+pub fn match_branch_subsequence(
+    branches: &[Branch],
+    needle: &str,
+    ignore_case: bool,
+) -> Vec<Branch> {
+    if needle.is_empty() {
+        return Vec::new();
+    }
+
+    let needle = fold_case(needle, ignore_case);
+    branches
+        .iter()
+        .filter(|b| is_subsequence(&fold_case(&b.name, ignore_case), &needle))
+        .cloned()
+        .collect()
+}
+
+/// Compute the Levenshtein edit distance between two strings
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b_chars.len()).collect();
+    let mut curr = vec![0; b_chars.len() + 1];
+
+    for (i, ca) in a.chars().enumerate() {
+        curr[0] = i + 1;
+        for (j, cb) in b_chars.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+
+    prev[b_chars.len()]
+}
+
+/// Maximum edit distance to tolerate for a needle, so that short patterns
+/// don't match everything
+pub fn default_max_distance(needle: &str) -> usize {
+    match needle.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+/// Match branches whose name, or any `/`-separated segment of it, is within
+/// `max_distance` edits of the needle. Results are sorted by increasing distance.
+pub fn match_branch_fuzzy_distance(
+    branches: &[Branch],
+    needle: &str,
+    max_distance: usize,
+    ignore_case: bool,
+) -> Vec<Branch> {
+    if needle.is_empty() {
+        return Vec::new();
+    }
+
+    let needle = fold_case(needle, ignore_case);
+    let mut matches: Vec<(usize, Branch)> = branches
+        .iter()
+        .filter_map(|b| {
+            let name = fold_case(&b.name, ignore_case);
+            let distance = std::iter::once(name.as_str())
+                .chain(name.split('/'))
+                .map(|candidate| levenshtein(candidate, &needle))
+                .min()?;
+            (distance <= max_distance).then(|| (distance, b.clone()))
+        })
+        .collect();
+
+    // Stable sort keeps ref order for equally distant branches
+    matches.sort_by_key(|(distance, _)| *distance);
+    matches.into_iter().map(|(_, b)| b).collect()
+}
+
+// Base scores for each kind of match, best first
+const EXACT_SCORE: u32 = 1000;
+const PREFIX_SCORE: u32 = 800;
+const SUBSTRING_SCORE: u32 = 600;
+const INITIALS_SCORE: u32 = 400;
+const SUBSEQUENCE_SCORE: u32 = 200;
+const DISTANCE_SCORE: u32 = 100;
+
+/// Score penalty per edit for typo-tolerant matches
+const DISTANCE_PENALTY: u32 = 10;
+
+/// Bonus for a substring match that starts at the beginning of a word
+const WORD_BOUNDARY_BONUS: u32 = 100;
+
+/// Maximum bonus for short branch names, so that `dev` prefers `develop`
+/// over `develop-old-experiment` when everything else is equal
+const SHORT_NAME_BONUS: u32 = 8;
+
+/// Bonus for anything that isn't a remote branch, so that a local branch
+/// is chosen over an equally good match among a remote's branches. It's as
+/// big as `DEFAULT_MIN_MARGIN` so that the local branch wins outright.
+const LOCAL_BRANCH_BONUS: u32 = 50;
+
+/// Default for how far the best match must lead the runner-up to be chosen
+/// automatically
+pub const DEFAULT_MIN_MARGIN: u32 = 50;
+
+/// The parts of a branch's score, for `--explain`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScoreBreakdown {
+    /// The kind of match, e.g. `substring` or `edit distance`
+    pub kind: &'static str,
+    /// The base score for that kind of match
+    pub base: u32,
+    /// Bonus for a substring match at the start of a word
+    pub word_boundary_bonus: u32,
+    /// Bonus for a short name
+    pub short_name_bonus: u32,
+    /// Bonus for not being a remote branch
+    pub local_bonus: u32,
+}
+
+impl ScoreBreakdown {
+    /// The score that the parts add up to
+    fn total(&self) -> u32 {
+        self.base + self.word_boundary_bonus + self.short_name_bonus + self.local_bonus
+    }
+}
+
+/// Score how well a branch matches the needle, or `None` if it doesn't match
+fn score_branch(
+    branch: &Branch,
+    needle: &str,
+    ignore_case: bool,
+    max_distance: usize,
+) -> Option<u32> {
+    explain_score(branch, needle, ignore_case, max_distance).map(|breakdown| breakdown.total())
+}
+
+/// Like `score_branch`, but with the parts that make up the score
+pub fn explain_score(
+    branch: &Branch,
+    needle: &str,
+    ignore_case: bool,
+    max_distance: usize,
+) -> Option<ScoreBreakdown> {
+    if needle.is_empty() {
+        return None;
+    }
+
+    let name = fold_case(&branch.name, ignore_case);
+    let needle = fold_case(needle, ignore_case);
+
+    let mut word_boundary_bonus = 0;
+    let (kind, base) = if name == needle {
+        ("exact", EXACT_SCORE)
+    } else if name.starts_with(&needle) {
+        ("prefix", PREFIX_SCORE)
+    } else if let Some(pos) = name.find(&needle) {
+        let at_word_start = split_words(&name).iter().any(|(offset, _)| *offset == pos);
+        if at_word_start {
+            word_boundary_bonus = WORD_BOUNDARY_BONUS;
+        }
+        ("substring", SUBSTRING_SCORE)
+    } else if find_initials(&name, &needle).is_some() {
+        ("initials", INITIALS_SCORE)
+    } else if is_subsequence(&name, &needle) {
+        ("subsequence", SUBSEQUENCE_SCORE)
+    } else {
+        let distance = std::iter::once(name.as_str())
+            .chain(name.split('/'))
+            .map(|candidate| levenshtein(candidate, &needle))
+            .min()?;
+        if distance > max_distance {
+            return None;
+        }
+        (
+            "edit distance",
+            DISTANCE_SCORE.saturating_sub(distance as u32 * DISTANCE_PENALTY),
+        )
+    };
+
+    let length = name.chars().count() as u32;
+    let local_bonus = if branch.is_remote() {
+        0
+    } else {
+        LOCAL_BRANCH_BONUS
+    };
+
+    Some(ScoreBreakdown {
+        kind,
+        base,
+        word_boundary_bonus,
+        short_name_bonus: SHORT_NAME_BONUS.saturating_sub(length / 4),
+        local_bonus,
+    })
+}
+
+/// Score the given branches against the needle, returning the matching ones
+/// sorted by descending score
+fn rank_matches(
+    branches: &[Branch],
+    needle: &str,
+    ignore_case: bool,
+    max_distance: usize,
+) -> Vec<(Branch, u32)> {
+    let mut ranked: Vec<(Branch, u32)> = branches
+        .iter()
+        .filter_map(|b| {
+            score_branch(b, needle, ignore_case, max_distance).map(|score| (b.clone(), score))
+        })
+        .collect();
+
+    // Stable sort keeps the incoming order for equally scored branches
+    ranked.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+    ranked
+}
+
+/// Match branches whose name matches a regular expression
+pub fn match_branch_regex(branches: &[Branch], regex: &Regex) -> Vec<Branch> {
+    branches
+        .iter()
+        .filter(|b| regex.is_match(&b.name))
+        .cloned()
+        .collect()
+}
+
+/// Match branches whose full name matches a shell-style glob
+pub fn match_branch_glob(branches: &[Branch], glob: &GlobMatcher) -> Vec<Branch> {
+    branches
+        .iter()
+        .filter(|b| glob.is_match(&b.name))
+        .cloned()
+        .collect()
+}
+
+/// Run the matching passes in order of strictness, stopping at the first pass
+/// that finds anything, and rank the result
+fn find_matches(
+    branches: &[Branch],
+    needle: &str,
+    ignore_case: bool,
+    threshold: Option<usize>,
+) -> Vec<(Branch, u32)> {
+    let max_distance = threshold.unwrap_or_else(|| default_max_distance(needle));
+
+    // Try exact match first
+    let mut matches = match_branch_exactly(branches, needle, ignore_case);
+    let mut pass = "exact";
+
+    // If no exact match, a unique prefix match wins outright
+    if matches.is_empty() {
+        let prefix_matches = match_branch_prefix(branches, needle, ignore_case);
+        if prefix_matches.len() == 1 {
+            matches = prefix_matches;
+        }
+        pass = "prefix";
+    }
+
+    // Otherwise, try substring match
+    if matches.is_empty() {
+        matches = match_branch_substring(branches, needle, ignore_case);
+        pass = "substring";
+    }
+
+    // A threshold of 0 turns fuzzy matching off, leaving only the exact,
+    // prefix and substring passes above, since no edits are allowed either
+    let fuzzy = threshold != Some(0);
+
+    // If no substring match, try matching the initials of words
+    if matches.is_empty() && fuzzy {
+        matches = match_branch_initials(branches, needle, ignore_case);
+        pass = "initials";
+    }
+
+    // If no initials match, try subsequence match
+    if matches.is_empty() && fuzzy {
+        matches = match_branch_subsequence(branches, needle, ignore_case);
+        pass = "subsequence";
+    }
+
+    // As a last resort, tolerate typos via edit distance
+    if matches.is_empty() {
+        matches = match_branch_fuzzy_distance(branches, needle, max_distance, ignore_case);
+        pass = "edit distance";
+    }
+
+    if matches.is_empty() {
+        log_verbose("No matching pass found any branches");
+    } else {
+        log_verbose(&format!(
+            "The {} pass matched {} branches",
+            pass,
+            matches.len()
+        ));
+    }
+
+    rank_matches(&matches, needle, ignore_case, max_distance)
+}
+
+/// Check whether a pattern could be an abbreviated or full commit hash
+pub fn looks_like_hash(s: &str) -> bool {
+    // Git never abbreviates hashes to fewer than 4 characters, and SHA-256
+    // hashes are 64 characters long
+    (4..=64).contains(&s.len()) && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Resolve a commit-ish to its full commit hash
+pub fn resolve_commit(git: &dyn GitRunner, commit: &str) -> Option<String> {
+    let spec = format!("{}^{{commit}}", commit);
+    git.run(&["rev-parse", "--verify", "--quiet", &spec])
+        .ok()
+        .map(|s| s.trim().to_string())
+}
+
+/// Describe what checking out a branch would do, for `--dry-run`
+pub fn describe_branch(branch: &Branch) -> String {
+    match branch.kind {
+        RefKind::Local => format!("local branch '{}'", branch.name),
+        RefKind::Remote => format!(
+            "remote branch '{}' (as a new local tracking branch)",
+            branch.name
+        ),
+        RefKind::Tag => format!("tag '{}' (in detached HEAD)", branch.name),
+        RefKind::Commit => format!("commit '{}' (in detached HEAD)", branch.name),
+    }
+}
+
+/// Check whether the working tree has uncommitted changes
+pub fn working_tree_is_dirty(git: &dyn GitRunner) -> bool {
+    git.run(&["status", "--porcelain", "--untracked-files=no"])
+        .is_ok_and(|s| !s.trim().is_empty())
+}
+
+/// Stash uncommitted changes
+pub fn stash_changes(git: &dyn GitRunner) -> Result<(), GitFuzzyError> {
+    git.run(&["stash", "push", "--message", "git-fuzzy autostash"])
+        .map(|_| ())
+}
+
+/// The git command used to move between branches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckoutCommand {
+    /// `git switch`, available since git 2.23
+    Switch,
+    /// `git checkout`, for older git
+    Checkout,
+}
+
+impl CheckoutCommand {
+    /// Use `git switch` if the installed git has it
+    pub fn detect(git: &dyn GitRunner) -> Self {
+        match get_git_version(git) {
+            Some(version) if version >= (2, 23) => CheckoutCommand::Switch,
+            _ => CheckoutCommand::Checkout,
+        }
+    }
+
+    /// The git subcommand
+    pub fn name(self) -> &'static str {
+        match self {
+            CheckoutCommand::Switch => "switch",
+            CheckoutCommand::Checkout => "checkout",
+        }
+    }
+
+    /// The flag that creates a new branch
+    fn create_flag(self) -> &'static str {
+        match self {
+            CheckoutCommand::Switch => "-c",
+            CheckoutCommand::Checkout => "-b",
+        }
+    }
+}
+
+/// Get the major and minor version of git, from output like
+/// `git version 2.39.3 (Apple Git-145)`
+fn get_git_version(git: &dyn GitRunner) -> Option<(u32, u32)> {
+    let output = git.run(&["--version"]).ok()?;
+    let version = output.trim().strip_prefix("git version ")?;
+    let mut parts = version.split(['.', ' ']);
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+/// Checkout a branch
+pub fn checkout_branch(
+    git: &dyn GitRunner,
+    command: CheckoutCommand,
+    branch: &Branch,
+    force: bool,
+    extra_args: &[String],
+) -> Result<(), GitFuzzyError> {
+    git.run_interactive(&checkout_branch_args(command, branch, force, extra_args))
+}
+
+/// The arguments that `checkout_branch` runs git with
+pub fn checkout_branch_args<'a>(
+    command: CheckoutCommand,
+    branch: &'a Branch,
+    force: bool,
+    extra_args: &'a [String],
+) -> Vec<&'a str> {
+    let mut args = vec![command.name()];
+
+    // Discard local changes if asked to
+    if force {
+        args.push("--force");
+    }
+
+    // If it's a remote branch, create a local tracking branch
+    if branch.is_remote() {
+        args.push("--track");
+    }
+
+    // Unlike checkout, switch only detaches HEAD when asked to
+    if command == CheckoutCommand::Switch && branch.kind == RefKind::Tag {
+        args.push("--detach");
+    }
+
+    args.extend(extra_args.iter().map(String::as_str));
+    args.push(&branch.name);
+    args
+}
+
+/// The arguments to create a new branch, from `base` or else from HEAD,
+/// and check it out
+pub fn create_branch_args<'a>(
+    command: CheckoutCommand,
+    name: &'a str,
+    base: Option<&'a str>,
+    extra_args: &'a [String],
+) -> Vec<&'a str> {
+    let mut args = vec![command.name()];
+    args.extend(extra_args.iter().map(String::as_str));
+    args.extend([command.create_flag(), name]);
+    args.extend(base);
+    args
+}
+
+/// The arguments to create a local branch tracking a remote branch and
+/// check it out
+pub fn create_tracking_branch_args<'a>(
+    command: CheckoutCommand,
+    local_name: &'a str,
+    remote_branch: &'a str,
+    force: bool,
+    extra_args: &'a [String],
+) -> Vec<&'a str> {
+    let mut args = vec![command.name()];
+    if force {
+        args.push("--force");
+    }
+    args.extend(extra_args.iter().map(String::as_str));
+    let create_flag = command.create_flag();
+    args.extend([create_flag, local_name, "--track", remote_branch]);
+    args
+}
+
+/// The arguments to set the upstream of the local branch `local_name` to
+/// `upstream`, e.g. `origin/feature/x`
+pub fn set_upstream_args<'a>(local_name: &'a str, upstream: &'a str) -> Vec<&'a str> {
+    vec!["branch", "--set-upstream-to", upstream, local_name]
+}
+
+/// Get the upstream of a local branch, like `origin/main`, if it has one
+pub fn get_upstream(git: &dyn GitRunner, local_name: &str) -> Option<String> {
+    let upstream_ref = format!("{}@{{upstream}}", local_name);
+    git.run(&["rev-parse", "--abbrev-ref", &upstream_ref])
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+/// Get the previously checked out branch (or commit), like `@{-1}`
+pub fn get_previous_branch(git: &dyn GitRunner) -> Option<String> {
+    git.run(&["rev-parse", "--abbrev-ref", "@{-1}"])
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+/// Delete a local branch, refusing to if it isn't merged unless `force` is
+/// set, like `git branch -d` and `-D`
+pub fn delete_branch(git: &dyn GitRunner, name: &str, force: bool) -> Result<(), GitFuzzyError> {
+    let flag = if force { "-D" } else { "-d" };
+    git.run_interactive(&["branch", flag, name])
+}
+
+/// Diff the working tree against a branch, with `extra_args` like `--stat`
+/// or paths passed on to git diff
+pub fn diff_against(
+    git: &dyn GitRunner,
+    name: &str,
+    extra_args: &[String],
+) -> Result<(), GitFuzzyError> {
+    let mut args = vec!["diff", name];
+    args.extend(extra_args.iter().map(String::as_str));
+    git.run_interactive(&args)
+}
+
+/// Show the history of a branch, with `extra_args` like `--oneline` passed
+/// on to git log
+pub fn show_log(
+    git: &dyn GitRunner,
+    name: &str,
+    extra_args: &[String],
+) -> Result<(), GitFuzzyError> {
+    let mut args = vec!["log"];
+    args.extend(extra_args.iter().map(String::as_str));
+    args.push(name);
+    git.run_interactive(&args)
+}
+
+/// Merge a branch into the current branch, with `extra_args` like `--no-ff`
+/// passed on to git merge
+pub fn merge_branch(
+    git: &dyn GitRunner,
+    name: &str,
+    extra_args: &[String],
+) -> Result<(), GitFuzzyError> {
+    let mut args = vec!["merge"];
+    args.extend(extra_args.iter().map(String::as_str));
+    args.push(name);
+    git.run_interactive(&args)
+}
+
+/// The arguments to check out the previously checked out branch, like
+/// `git checkout -`
+pub fn checkout_previous_args(force: bool, extra_args: &[String]) -> Vec<&str> {
+    // The previous checkout may have been a detached commit, which
+    // `git switch -` refuses, so this always uses checkout
+    let mut args = vec!["checkout"];
+    if force {
+        args.push("--force");
+    }
+    args.extend(extra_args.iter().map(String::as_str));
+    args.push("-");
+    args
+}
+
+/// The arguments to check out a commit in detached HEAD
+pub fn checkout_commit_args<'a>(
+    command: CheckoutCommand,
+    commit: &'a str,
+    force: bool,
+    extra_args: &'a [String],
+) -> Vec<&'a str> {
+    let mut args = vec![command.name(), "--detach"];
+    if force {
+        args.push("--force");
+    }
+    args.extend(extra_args.iter().map(String::as_str));
+    args.push(commit);
+    args
+}
+
+/// The arguments to check out a branch in a new worktree at `path`, leaving
+/// the current checkout alone. A remote branch gets a local tracking branch
+/// named after it without its remote, one of `remotes`, and tags and commits
+/// are checked out in detached HEAD.
+pub fn add_worktree_args<'a>(
+    path: &'a str,
+    branch: &'a Branch,
+    remotes: &[String],
+    extra_args: &'a [String],
+) -> Result<Vec<&'a str>, GitFuzzyError> {
+    let mut args = vec!["worktree", "add"];
+    args.extend(extra_args.iter().map(String::as_str));
+
+    match branch.kind {
+        RefKind::Local => args.extend([path, branch.name.as_str()]),
+        RefKind::Remote => {
+            let Some((_, local_name)) = split_remote_branch(&branch.name, remotes) else {
+                return Err(GitFuzzyError::Other(format!(
+                    "Could not determine the remote of branch '{}'",
+                    branch.name
+                )));
+            };
+            args.extend(["--track", "-b", local_name, path, &branch.name]);
+        }
+        RefKind::Tag | RefKind::Commit => args.extend(["--detach", path, branch.name.as_str()]),
+    }
+    Ok(args)
+}
+
+/// Highlight every occurrence of the needle in a branch name
+pub fn highlight_match(branch_name: &str, needle: &str, ignore_case: bool) -> String {
+    if needle.is_empty() {
+        return branch_name.to_string();
+    }
+
+    // Positions are found in the folded name, so translate them back to the
+    // original, whose characters may be longer or shorter. Slicing through a
+    // multibyte character would panic, so only slice on char boundaries.
+    let (folded_name, offsets) = fold_case_with_offsets(branch_name, ignore_case);
+    let folded_needle = fold_case(needle, ignore_case);
+    let mut result = String::new();
+    let mut end = 0;
+    for (pos, matched) in folded_name.match_indices(&folded_needle) {
+        let start = offsets[pos];
+        let match_end = offsets[pos + matched.len()];
+        let (Some(before), Some(matched)) = (
+            branch_name.get(end..start),
+            branch_name.get(start..match_end),
+        ) else {
+            return branch_name.to_string();
+        };
+        result.push_str(before);
+        result.push_str(&paint_match(matched).to_string());
+        end = match_end;
+    }
+    result.push_str(&branch_name[end..]);
+    result
+}
+
+/// Highlight every occurrence of each of several needles, for patterns that
+/// must all match. Overlapping and adjacent occurrences are highlighted as
+/// one span.
+pub fn highlight_tokens(branch_name: &str, needles: &[String], ignore_case: bool) -> String {
+    let (folded_name, offsets) = fold_case_with_offsets(branch_name, ignore_case);
+    let mut highlighted = vec![false; branch_name.len()];
+    for needle in needles.iter().filter(|n| !n.is_empty()) {
+        let folded_needle = fold_case(needle, ignore_case);
+        for (pos, matched) in folded_name.match_indices(&folded_needle) {
+            highlighted[offsets[pos]..offsets[pos + matched.len()]].fill(true);
+        }
+    }
+
+    let mut result = String::new();
+    let mut span = String::new();
+    for (i, c) in branch_name.char_indices() {
+        if highlighted[i] {
+            span.push(c);
+            continue;
+        }
+        if !span.is_empty() {
+            result.push_str(&paint_match(&span).to_string());
+            span.clear();
+        }
+        result.push(c);
+    }
+    if !span.is_empty() {
+        result.push_str(&paint_match(&span).to_string());
+    }
+    result
+}
+
+/// Shorten `text`, which may be colored, to at most `width` visible
+/// characters by replacing what doesn't fit with an ellipsis. The window is
+/// moved along to keep the start of the first highlighted match in view,
+/// and color codes are all kept so that nothing bleeds into the next line.
+pub fn truncate_highlighted(text: &str, width: usize) -> String {
+    enum Piece<'a> {
+        Code(&'a str),
+        Char(char),
+    }
+
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if let Some(code) = rest.strip_prefix("\x1b[") {
+            let end = code
+                .find(|c: char| ('@'..='~').contains(&c))
+                .map_or(rest.len(), |i| i + 3);
+            pieces.push(Piece::Code(&rest[..end]));
+            rest = &rest[end..];
+        } else {
+            pieces.push(Piece::Char(c));
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    let visible = pieces
+        .iter()
+        .filter(|p| matches!(p, Piece::Char(_)))
+        .count();
+    if visible <= width || width < 3 {
+        return text.to_string();
+    }
+
+    // Find the visible span of the first highlight, going by the codes that
+    // start and end it
+    let painted = paint_match("x").to_string();
+    let start_code = painted.split('x').next().unwrap_or_default();
+    let mut match_start = None;
+    let mut match_end = None;
+    let mut chars_seen = 0;
+    for piece in &pieces {
+        match piece {
+            Piece::Code(code) if match_start.is_none() && !start_code.is_empty() => {
+                if *code == start_code {
+                    match_start = Some(chars_seen);
+                }
+            }
+            Piece::Code(code) if match_start.is_some() && match_end.is_none() => {
+                if *code == "\x1b[0m" {
+                    match_end = Some(chars_seen);
+                }
+            }
+            Piece::Code(_) => {}
+            Piece::Char(_) => chars_seen += 1,
+        }
+    }
+    let (match_start, match_end) = match match_start {
+        Some(start) => (start, match_end.unwrap_or(visible)),
+        None => (0, 0),
+    };
+
+    // Cut the end if the match fits before it, else the start, and both if
+    // the match is in the middle of a long name
+    let (keep_start, keep_end) = if match_end < width || match_start == 0 {
+        (0, width - 1)
+    } else if match_start + width > visible {
+        (visible + 1 - width, visible)
+    } else {
+        (match_start, match_start + width - 2)
+    };
+
+    let mut result = String::new();
+    let mut index = 0;
+    for piece in &pieces {
+        match piece {
+            Piece::Code(code) => result.push_str(code),
+            Piece::Char(c) => {
+                if (keep_start..keep_end).contains(&index) {
+                    result.push(*c);
+                }
+                index += 1;
+                // The leading ellipsis goes straight after the characters it
+                // replaces, before any color starting with the window
+                if index == keep_start || index == keep_end && keep_end < visible {
+                    result.push('…');
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Highlight the first span matched by a regular expression
+fn highlight_regex(branch_name: &str, regex: &Regex) -> String {
+    if let Some(m) = regex.find(branch_name) {
+        let before = &branch_name[..m.start()];
+        let after = &branch_name[m.end()..];
+        format!("{}{}{}", before, paint_match(m.as_str()), after)
+    } else {
+        branch_name.to_string()
+    }
+}
+
+/// Highlight an anchored literal at the start of the name, or else at the
+/// end
+fn highlight_anchored(branch_name: &str, literal: &str, start: bool, ignore_case: bool) -> String {
+    let (folded_name, offsets) = fold_case_with_offsets(branch_name, ignore_case);
+    let folded_literal = fold_case(literal, ignore_case);
+    let (from, to) = if start && folded_name.starts_with(&folded_literal) {
+        (0, offsets[folded_literal.len()])
+    } else if !start && folded_name.ends_with(&folded_literal) {
+        (
+            offsets[folded_name.len() - folded_literal.len()],
+            branch_name.len(),
+        )
+    } else {
+        return branch_name.to_string();
+    };
+    match (
+        branch_name.get(..from),
+        branch_name.get(from..to),
+        branch_name.get(to..),
+    ) {
+        (Some(before), Some(matched), Some(after)) => {
+            format!("{}{}{}", before, paint_match(matched), after)
+        }
+        _ => branch_name.to_string(),
+    }
+}
+
+/// Highlight the literal prefix of a glob, i.e. everything before its first
+/// wildcard
+fn highlight_glob(branch_name: &str, glob: &GlobMatcher, ignore_case: bool) -> String {
+    let pattern = glob.glob().glob();
+    let literal_len = pattern
+        .find(['*', '?', '[', '{', '\\'])
+        .unwrap_or(pattern.len());
+    highlight_match(branch_name, &pattern[..literal_len], ignore_case)
+}
+
+/// Highlight the initial characters of the words matched by the needle
+fn highlight_initials(branch_name: &str, needle: &str, ignore_case: bool) -> String {
+    let (folded_name, folded_offsets) = fold_case_with_offsets(branch_name, ignore_case);
+    let folded_needle = fold_case(needle, ignore_case);
+    let Some(offsets) = find_initials(&folded_name, &folded_needle) else {
+        return branch_name.to_string();
+    };
+    let offsets: Vec<usize> = offsets.iter().map(|&i| folded_offsets[i]).collect();
+
+    let mut result = String::new();
+    for (i, c) in branch_name.char_indices() {
+        if offsets.contains(&i) {
+            result.push_str(&paint_match(&c.to_string()).to_string());
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Highlight each character of a subsequence match, taking the earliest
+/// match of each needle character. Contiguous matches are highlighted as a
+/// whole by `highlight_match`.
+fn highlight_subsequence(branch_name: &str, needle: &str, ignore_case: bool) -> String {
+    let folded_name = fold_case(branch_name, ignore_case);
+    let folded_needle = fold_case(needle, ignore_case);
+    if folded_name.contains(&folded_needle) {
+        return highlight_match(branch_name, needle, ignore_case);
+    }
+    if !is_subsequence(&folded_name, &folded_needle) {
+        return branch_name.to_string();
+    }
+
+    let mut needle_chars = folded_needle.chars().peekable();
+    let mut result = String::new();
+    for c in branch_name.chars() {
+        let folded = fold_case(c.encode_utf8(&mut [0; 4]), ignore_case);
+        match needle_chars.peek() {
+            Some(&n) if folded.starts_with(n) => {
+                needle_chars.next();
+                result.push_str(&paint_match(&c.to_string()).to_string());
+            }
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Highlight whichever part of a branch name the needle matched
+fn highlight_branch(branch_name: &str, needle: &str, ignore_case: bool) -> String {
+    let folded_name = fold_case(branch_name, ignore_case);
+    let folded_needle = fold_case(needle, ignore_case);
+    if folded_name.contains(&folded_needle) {
+        highlight_match(branch_name, needle, ignore_case)
+    } else if find_initials(&folded_name, &folded_needle).is_some() {
+        highlight_initials(branch_name, needle, ignore_case)
+    } else {
+        highlight_subsequence(branch_name, needle, ignore_case)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers every git command with the same canned output
+    struct FakeGit(&'static str);
+
+    impl GitRunner for FakeGit {
+        fn run(&self, _args: &[&str]) -> Result<String, GitFuzzyError> {
+            Ok(self.0.to_string())
+        }
+    }
+
+    #[test]
+    fn get_all_branches_skips_remote_head() {
+        let git = FakeGit(
+            "refs/heads/main\tmain\t100\t\n\
+             refs/heads/fix-head-parsing\tfix-head-parsing\t200\t\n\
+             refs/remotes/origin/HEAD\torigin\t100\t\n\
+             refs/remotes/origin/main\torigin/main\t100\t\n",
+        );
+        let names: Vec<String> = get_all_branches(&git)
+            .unwrap()
+            .into_iter()
+            .map(|b| b.name)
+            .collect();
+        assert_eq!(names, ["main", "fix-head-parsing", "origin/main"]);
+    }
+
+    /// Answers `git remote` with the given remotes, and any other command
+    /// with the given refs
+    struct FakeRepo {
+        remotes: &'static str,
+        refs: &'static str,
+    }
+
+    impl GitRunner for FakeRepo {
+        fn run(&self, args: &[&str]) -> Result<String, GitFuzzyError> {
+            match args {
+                ["remote"] => Ok(self.remotes.to_string()),
+                _ => Ok(self.refs.to_string()),
+            }
+        }
+    }
+
+    fn tracking_branch_names(git: &dyn GitRunner, preferred_remotes: &[&str]) -> Vec<String> {
+        let preferred_remotes: Vec<String> =
+            preferred_remotes.iter().map(|r| r.to_string()).collect();
+        get_tracking_branches(git, &preferred_remotes)
+            .unwrap()
+            .into_iter()
+            .map(|b| b.name)
+            .collect()
+    }
+
+    #[test]
+    fn tracking_branches_keep_slashes_in_branch_names() {
+        let git = FakeRepo {
+            remotes: "origin\n",
+            refs: "refs/heads/feature/foo\tfeature/foo\t100\t\n\
+                   refs/remotes/origin/feature/foo\torigin/feature/foo\t100\t\n\
+                   refs/remotes/origin/feature/bar\torigin/feature/bar\t100\t\n",
+        };
+        assert_eq!(
+            tracking_branch_names(&git, &["origin"]),
+            ["feature/foo", "origin/feature/bar"]
+        );
+    }
+
+    #[test]
+    fn tracking_branches_strip_remotes_containing_slashes() {
+        let git = FakeRepo {
+            remotes: "my/remote\norigin\n",
+            refs: "refs/heads/feature\tfeature\t100\t\n\
+                   refs/heads/remote/topic\tremote/topic\t100\t\n\
+                   refs/remotes/my/remote/feature\tmy/remote/feature\t100\t\n\
+                   refs/remotes/my/remote/topic\tmy/remote/topic\t100\t\n\
+                   refs/remotes/origin/topic\torigin/topic\t100\t\n",
+        };
+        // `my/remote/feature` belongs to the local `feature`, while
+        // `my/remote/topic` is `topic`, not the local `remote/topic`, and
+        // only the preferred remote's copy of it is kept
+        assert_eq!(
+            tracking_branch_names(&git, &["origin"]),
+            ["feature", "remote/topic", "origin/topic"]
+        );
+        assert_eq!(
+            tracking_branch_names(&git, &["my/remote"]),
+            ["feature", "remote/topic", "my/remote/topic"]
+        );
+    }
+
+    #[test]
+    fn remote_branches_split_on_remotes_containing_slashes() {
+        let git = FakeRepo {
+            remotes: "my/remote\n",
+            refs: "refs/remotes/my/remote/topic-a\tmy/remote/topic-a\t100\t\n\
+                   refs/remotes/gone/topic-b\tgone/topic-b\t100\t\n",
+        };
+        let branches = get_remote_branches(&git).unwrap();
+        let parts: Vec<(Option<&str>, &str)> = branches
+            .iter()
+            .map(|b| (b.remote.as_deref(), b.short_name()))
+            .collect();
+        // A remote that isn't configured any more ends at the first slash
+        assert_eq!(
+            parts,
+            [(Some("my/remote"), "topic-a"), (Some("gone"), "topic-b")]
+        );
+    }
+
+    /// Fails every command the way a missing git executable does
+    struct MissingGit;
+
+    impl GitRunner for MissingGit {
+        fn run(&self, _args: &[&str]) -> Result<String, GitFuzzyError> {
+            Err(spawn_error(io::Error::from(io::ErrorKind::NotFound)))
+        }
+    }
+
+    #[test]
+    fn missing_git_is_reported_as_not_installed() {
+        let error = find_git_directory(&MissingGit).unwrap_err();
+        assert!(matches!(error, GitFuzzyError::GitNotFound));
+        assert_eq!(
+            error.to_string(),
+            "git executable not found on PATH; please install git"
+        );
+
+        let branch = Branch::new("main".to_string(), RefKind::Local, 0);
+        let error =
+            checkout_branch(&MissingGit, CheckoutCommand::Switch, &branch, false, &[]).unwrap_err();
+        assert!(matches!(error, GitFuzzyError::GitNotFound));
+
+        let denied = spawn_error(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(matches!(denied, GitFuzzyError::GitSpawn(_)));
+    }
+
+    fn local_branches(names: &[&str]) -> Vec<Branch> {
+        names
+            .iter()
+            .map(|name| Branch::new(name.to_string(), RefKind::Local, 0))
+            .collect()
+    }
+
+    fn matched_names(ranked: &[(Branch, u32)]) -> Vec<&str> {
+        ranked.iter().map(|(b, _)| b.name.as_str()).collect()
+    }
+
+    #[test]
+    fn anchored_patterns_pin_the_start_the_end_or_both() {
+        let branches = local_branches(&["feat", "feat-feat", "feat-login", "old-feat"]);
+        let cases: &[(&str, &[&str])] = &[
+            ("^feat", &["feat", "feat-feat", "feat-login"]),
+            ("feat$", &["feat", "feat-feat", "old-feat"]),
+            ("^feat$", &["feat"]),
+            ("^login$", &[]),
+        ];
+        for (pattern, expected) in cases {
+            let matcher = Matcher::anchored(pattern).unwrap();
+            let ranked = matcher.find_matches(&branches, "", false, None);
+            assert_eq!(matched_names(&ranked), *expected, "pattern {}", pattern);
+        }
+    }
+
+    #[test]
+    fn passes_stop_at_the_strictest_one_that_matches() {
+        let branches = local_branches(&[
+            "main",
+            "develop",
+            "develop-old",
+            "feature/user-profile",
+            "fix-login",
+            "hotfix-logon",
+        ]);
+        let cases: &[(&str, &str, &[&str])] = &[
+            // An exact match beats a prefix match
+            ("develop", "exact", &["develop"]),
+            ("feature/u", "prefix", &["feature/user-profile"]),
+            // Several prefix matches fall through to the substring pass,
+            // which still scores them as prefixes
+            ("dev", "prefix", &["develop", "develop-old"]),
+            ("login", "substring", &["fix-login"]),
+            // The initials of `fix-login`, but a subsequence of more
+            ("fl", "initials", &["fix-login"]),
+            ("mn", "subsequence", &["main"]),
+            ("maim", "edit distance", &["main"]),
+            ("xyz", "", &[]),
+        ];
+        for (needle, kind, expected) in cases {
+            let ranked = Matcher::Fuzzy.find_matches(&branches, needle, false, None);
+            assert_eq!(matched_names(&ranked), *expected, "needle {}", needle);
+            if let Some((best, _)) = ranked.first() {
+                let breakdown = explain_score(best, needle, false, 1).unwrap();
+                assert_eq!(breakdown.kind, *kind, "needle {}", needle);
+            }
+        }
+    }
+
+    #[test]
+    fn threshold_zero_skips_the_fuzzy_passes() {
+        let branches = local_branches(&["main", "fix-login", "hotfix-logon"]);
+        let cases: &[(&str, &[&str])] = &[
+            ("main", &["main"]),
+            ("login", &["fix-login"]),
+            ("fl", &[]),
+            ("mn", &[]),
+            ("maim", &[]),
+        ];
+        for (needle, expected) in cases {
+            let ranked = Matcher::Fuzzy.find_matches(&branches, needle, false, Some(0));
+            assert_eq!(matched_names(&ranked), *expected, "needle {}", needle);
+        }
+    }
+
+    #[test]
+    fn pick_unique_needs_a_clear_lead() {
+        // Scores of the matches `a`, `b` and so on, best first
+        let cases: &[(&[u32], u32, Option<&str>)] = &[
+            (&[], DEFAULT_MIN_MARGIN, None),
+            (&[100], DEFAULT_MIN_MARGIN, Some("a")),
+            (&[857, 807], DEFAULT_MIN_MARGIN, Some("a")),
+            (&[857, 808], DEFAULT_MIN_MARGIN, None),
+            (&[857, 857, 857], 0, Some("a")),
+            (&[857, 0], u32::MAX, None),
+        ];
+        for (scores, min_margin, expected) in cases {
+            let names = local_branches(&["a", "b", "c"]);
+            let ranked: Vec<(Branch, u32)> =
+                names.into_iter().zip(scores.iter().copied()).collect();
+            let picked = pick_unique(&ranked, *min_margin).map(|b| b.name.as_str());
+            assert_eq!(picked, *expected, "scores {:?}", scores);
+        }
+    }
+
+    #[test]
+    fn local_branches_beat_equally_good_remote_ones() {
+        let local = Branch::new("fix-login".to_string(), RefKind::Local, 0);
+        let mut remote = Branch::new("origin/fix-login".to_string(), RefKind::Remote, 0);
+        remote.remote = Some("origin".to_string());
+
+        let local_score = explain_score(&local, "login", false, 1).unwrap();
+        assert_eq!(local_score.local_bonus, LOCAL_BRANCH_BONUS);
+        assert_eq!(
+            explain_score(&remote, "login", false, 1)
+                .unwrap()
+                .local_bonus,
+            0
+        );
+
+        // The bonus alone is enough to pick the local branch
+        let ranked = Matcher::Fuzzy.find_matches(&[remote, local], "login", false, None);
+        assert_eq!(matched_names(&ranked), ["fix-login", "origin/fix-login"]);
+        let picked = pick_unique(&ranked, DEFAULT_MIN_MARGIN).map(|b| b.name.as_str());
+        assert_eq!(picked, Some("fix-login"));
+    }
+
+    #[test]
+    fn narrowing_happens_before_matching() {
+        let branches = local_branches(&[
+            "feature-old",
+            "feature-2024",
+            "my-feature-2024",
+            "dependabot/feature-x",
+        ]);
+        // The further patterns and the `--not` ones, separated by spaces
+        let cases: &[(&str, &str, &str, &[&str])] = &[
+            // Two prefix matches, until one of them is narrowed away
+            ("feature", "2024", "", &["feature-2024"]),
+            ("feature", "", "2024", &["feature-old"]),
+            ("feature", "", "dependabot old", &["feature-2024"]),
+            ("feat", "x", "", &["dependabot/feature-x"]),
+            ("feat", "2024", "my", &["feature-2024"]),
+            ("feat", "old", "old", &[]),
+        ];
+        for (needle, required, excluded, expected) in cases {
+            let required: Vec<String> = required.split_whitespace().map(String::from).collect();
+            let excluded: Vec<String> = excluded.split_whitespace().map(String::from).collect();
+            let wanted = require_substrings(&branches, &required, false);
+            let narrowed = exclude_substrings(&wanted, &excluded, false);
+            let ranked = Matcher::Fuzzy.find_matches(&narrowed, needle, false, None);
+            assert_eq!(
+                matched_names(&ranked),
+                *expected,
+                "needle {} with {:?} but not {:?}",
+                needle,
+                required,
+                excluded
+            );
+        }
+
+        let wanted = require_substrings(&branches, &["OLD".to_string()], true);
+        let names: Vec<&str> = wanted.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, ["feature-old"]);
+    }
+
+    #[test]
+    fn keys_map_names_before_matching() {
+        let mut branches = local_branches(&["team/a/login", "team/b/login-page", "login-team/x"]);
+        let mut remote = Branch::new("origin/feature/logout".to_string(), RefKind::Remote, 0);
+        remote.remote = Some("origin".to_string());
+        branches.push(remote);
+        branches.push(Branch::new("feature/login".to_string(), RefKind::Local, 0));
+
+        let whole = Matcher::Fuzzy.find_matches(&branches, "login", false, None);
+        assert_eq!(matched_names(&whole), ["login-team/x"]);
+
+        let by_basename = Matcher::Fuzzy.find_matches_by(&branches, "login", false, None, |b| {
+            branch_basename(&b.name)
+        });
+        assert_eq!(
+            matched_names(&by_basename),
+            ["team/a/login", "feature/login"]
+        );
+
+        let prefixes = ["feature/".to_string()];
+        let cases: &[(&str, &[&str])] = &[
+            ("login", &["feature/login"]),
+            ("logout", &["origin/feature/logout"]),
+            ("team/a/login", &["team/a/login"]),
+        ];
+        for (needle, expected) in cases {
+            let ranked = Matcher::Fuzzy.find_matches_by(&branches, needle, false, None, |b| {
+                strip_branch_prefix(b, &prefixes)
+            });
+            assert_eq!(matched_names(&ranked), *expected, "needle {}", needle);
+        }
+    }
+
+    #[test]
+    fn display_name_shows_remote_and_kind_plainly() {
+        let mut remote = Branch::new("origin/team/feature-x".to_string(), RefKind::Remote, 0);
+        remote.remote = Some("origin".to_string());
+        let cases = [
+            (
+                Branch::new("feature-x".to_string(), RefKind::Local, 0),
+                "feature-x",
+            ),
+            (remote, "team/feature-x (origin)"),
+            (
+                Branch::new("v1.0".to_string(), RefKind::Tag, 0),
+                "v1.0 (tag)",
+            ),
+            (
+                Branch::new("620a729".to_string(), RefKind::Commit, 0),
+                "620a729 (commit)",
+            ),
+        ];
+        for (branch, expected) in cases {
+            assert_eq!(branch.display_name(), expected);
+        }
+    }
+
+    #[test]
+    fn ignore_case_folds_unicode() {
+        let branches = [Branch::new("RÉSUMÉ".to_string(), RefKind::Local, 0)];
+        let matches = Matcher::Fuzzy.find_matches(&branches, "résumé", true, None);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(match_branch_substring(&branches, "sumé", true).len(), 1);
+        assert!(match_branch_substring(&branches, "sumé", false).is_empty());
+    }
+
+    #[test]
+    fn smartcase_detects_unicode_uppercase() {
+        assert!(use_ignore_case("résumé", true));
+        assert!(!use_ignore_case("rÉsumé", true));
+        assert!(!use_ignore_case("résumé", false));
+    }
+
+    #[test]
+    fn highlight_match_translates_unicode_folding() {
+        colored::control::set_override(true);
+        assert_eq!(
+            highlight_match("RÉSUMÉ-draft", "résumé", true),
+            format!("{}-draft", "RÉSUMÉ".green().bold())
+        );
+        // 'İ' lowercases to two characters, shifting everything after it
+        assert_eq!(
+            highlight_match("İstanbul-trip", "trip", true),
+            format!("İstanbul-{}", "trip".green().bold())
+        );
+    }
+
+    #[test]
+    fn truncate_highlighted_keeps_ellipsis_out_of_colors() {
+        colored::control::set_override(true);
+        let name = format!("team/project/{}-with-a-long-tail", "login".green().bold());
+        assert_eq!(
+            truncate_highlighted(&name, 12),
+            format!("…{}-with…", "login".green().bold())
+        );
+    }
+
+    #[test]
+    fn highlight_match_handles_multibyte_names() {
+        colored::control::set_override(true);
+        assert_eq!(
+            highlight_match("fix/café-crème", "crème", false),
+            format!("fix/café-{}", "crème".green().bold())
+        );
+        assert_eq!(
+            highlight_match("Über-Feature", "feature", true),
+            format!("Über-{}", "Feature".green().bold())
+        );
+    }
+}
@@ -1,276 +1,2070 @@
-use clap::Parser;
-use colored::Colorize;
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use colored::{Color, Colorize};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{self, ClearType};
+use crossterm::{cursor, execute, queue};
+use git_fuzzy::{
+    add_worktree_args, branch_basename, branch_recency, checkout_branch_args, checkout_commit_args,
+    checkout_previous_args, create_branch_args, create_tracking_branch_args, default_max_distance,
+    delete_branch, describe_branch, diff_against, exclude_substrings, explain_score, fetch_remotes,
+    find_git_directory, get_branch_descriptions, get_current_branch, get_default_remote,
+    get_git_refs, get_git_remotes, get_head_commit, get_local_branches, get_previous_branch,
+    get_refs, get_remote_branches, get_upstream, highlight_tokens, is_bare_repository,
+    is_detached_head, is_linked_worktree, log_verbose, looks_like_hash, merge_branch, pick_unique,
+    prefer_local_branches, require_substrings, resolve_commit, select_tracking_branches,
+    set_highlight_color, set_upstream_args, set_verbose, show_log, split_remote_branch,
+    stash_changes, strip_branch_prefix, truncate_highlighted, use_ignore_case,
+    working_tree_is_dirty, Branch, CheckoutCommand, GitFuzzyError, GitRunner, Matcher, RefKind,
+    SystemGit, DEFAULT_MIN_MARGIN,
+};
+use serde::Serialize;
 use std::env;
-use std::path::PathBuf;
-use std::process::{exit, Command};
+use std::ffi::OsString;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::path::{Path, PathBuf};
+use std::process::{exit, Command, Stdio};
+use std::time::Duration;
 
 /// A git tool that simplifies branch checkout by allowing partial branch name matching
 #[derive(Parser)]
 #[command(name = "git-fuzzy")]
 #[command(version)]
 #[command(about = "Fuzzy git branch checkout", long_about = None)]
+#[command(args_override_self = true)]
+#[command(disable_help_subcommand = true)]
 struct Cli {
-    /// Branch name or pattern to match (e.g., 'dev' to match 'develop'). If not provided, lists all local branches alphabetically.
-    pattern: Option<String>,
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    /// Branch name or pattern to match (e.g., 'dev' to match 'develop'). Any further patterns must also appear in the branch name, e.g. 'feature 2024'. If not provided, lists all local branches alphabetically.
+    #[arg(value_name = "PATTERN")]
+    patterns: Vec<String>,
+
+    /// Read the patterns from the first line of stdin, for use in pipelines
+    #[arg(long, conflicts_with = "patterns")]
+    stdin: bool,
+
+    /// Collect remote branches up front, instead of only when no local branch matches, so that ambiguity is always reported the same way
+    #[arg(long, overrides_with_all = ["local_only", "offline"])]
+    all_refs: bool,
+
+    /// Only consider local branches, ignoring remotes
+    #[arg(short = 'L', long)]
+    local_only: bool,
+
+    /// Never touch remotes: only consider local branches (and tags with --tags), and skip --fetch
+    #[arg(long, overrides_with = "remote_only")]
+    offline: bool,
+
+    /// Only consider remote-tracking branches, even if a local branch has the same name
+    #[arg(short = 'R', long, overrides_with = "local_only")]
+    remote_only: bool,
+
+    /// Remotes to prefer, in order, when several have a branch of the same name. Defaults to git's checkout.defaultRemote, else origin.
+    #[arg(long, value_name = "REMOTE", value_delimiter = ',')]
+    prefer_remote: Vec<String>,
+
+    /// Leave out matches containing TEXT, e.g. `--not dependabot`. Can be repeated.
+    #[arg(long = "not", value_name = "TEXT")]
+    not: Vec<String>,
+
+    /// Leave out the current branch, unless it's the only match
+    #[arg(long)]
+    exclude_current: bool,
+
+    /// Match case-insensitively, unless the pattern contains an uppercase letter (smartcase)
+    #[arg(short = 'i', long, global = true)]
+    ignore_case: bool,
+
+    /// How far the best match's score must lead the runner-up's to be checked out automatically
+    #[arg(long, value_name = "SCORE", default_value_t = DEFAULT_MIN_MARGIN)]
+    min_margin: u32,
+
+    /// Only check out a branch whose name matches the pattern exactly, never guessing or falling back to a commit
+    #[arg(short = 'e', long, global = true, overrides_with_all = ["regex", "glob"])]
+    exact: bool,
+
+    /// Treat the pattern as a regular expression (e.g. '^feature/.*-2024$')
+    #[arg(short = 'r', long, global = true)]
+    regex: bool,
+
+    /// Treat the pattern as a shell-style glob (e.g. 'feature/*' or 'release-?.0')
+    #[arg(short = 'g', long, global = true, overrides_with = "regex")]
+    glob: bool,
+
+    /// Match the pattern only against the part of each branch name after its last slash, e.g. 'feature-login' in 'team/project/feature-login'
+    #[arg(short = 'b', long)]
+    basename: bool,
+
+    /// Ignore PREFIX at the start of branch names when matching, e.g. 'feature/' so that 'login' matches 'feature/login' exactly. Can be repeated.
+    #[arg(long, value_name = "PREFIX")]
+    strip_prefix: Vec<String>,
+
+    /// Ignore common branch-type prefixes like 'feature/', 'bugfix/' and 'hotfix/' when matching
+    #[arg(long)]
+    smart_prefix: bool,
+
+    /// Choose between ambiguous matches with an arrow-key picker instead of a numbered menu
+    #[arg(long)]
+    picker: bool,
+
+    /// Choose between ambiguous matches with fzf
+    #[arg(long, overrides_with = "picker")]
+    fzf: bool,
+
+    /// Check out the most recently used of the best matches instead of asking when several match equally well
+    #[arg(short = '1', long)]
+    first: bool,
+
+    /// Fail when no branch matches instead of trying the pattern as a commit, so a typo never leaves you in detached HEAD. Short for --on-no-match error.
+    #[arg(long, overrides_with = "on_no_match")]
+    no_commit_fallback: bool,
+
+    /// What to do when no branch matches: fail, try the pattern as a commit, or create a branch named after it. Whichever of this, --create and --no-commit-fallback comes last wins.
+    #[arg(long, value_name = "ACTION", default_value = "commit", value_parser = ["error", "commit", "create"])]
+    on_no_match: String,
+
+    /// Create a branch named after the pattern if no branch matches, instead of trying it as a commit
+    #[arg(short = 'c', long, overrides_with_all = ["on_no_match", "no_commit_fallback"])]
+    create: bool,
+
+    /// Like --create, but branch off BASE instead of HEAD. BASE is matched like the pattern and must resolve to a single branch, or else be a commit.
+    #[arg(long, value_name = "BASE", overrides_with_all = ["on_no_match", "no_commit_fallback"])]
+    create_from: Option<String>,
+
+    /// Check out pull request N from its `refs/pull/N/head` ref in detached HEAD, or as a new `pr-N` branch with --create
+    #[arg(long, value_name = "N", conflicts_with_all = ["patterns", "create_from", "worktree"])]
+    pr: Option<u64>,
+
+    /// Where --pr finds pull requests: GitHub's `refs/pull/` or GitLab's `refs/merge-requests/`
+    #[arg(long, value_name = "FORGE", default_value = "github", value_parser = ["github", "gitlab"])]
+    forge: String,
+
+    /// Check out the match in a new worktree at PATH instead of switching in place
+    #[arg(long, value_name = "PATH", overrides_with_all = ["create", "create_from"])]
+    worktree: Option<PathBuf>,
+
+    /// When a remote branch matches, explicitly create a local branch of the same name tracking it
+    #[arg(short = 't', long)]
+    track: bool,
+
+    /// After checking out a branch with an upstream, bring it up to date with `git pull --ff-only`
+    #[arg(long)]
+    pull: bool,
+
+    /// Like --track, but set the new local branch's upstream to the same branch on REMOTE instead of the matched remote
+    #[arg(long, value_name = "REMOTE")]
+    set_upstream_to: Option<String>,
+
+    /// Log git commands and matching decisions to stderr
+    #[arg(short = 'v', long)]
+    verbose: bool,
+
+    /// Suppress informational messages. Errors and --dry-run output are still printed
+    #[arg(short = 'q', long)]
+    quiet: bool,
+
+    /// Disable colored output. Colors are also disabled by NO_COLOR or when not writing to a terminal
+    #[arg(long)]
+    no_color: bool,
+
+    /// Highlight matches in this color, e.g. 'blue' or 'bright magenta', instead of green. Defaults to GIT_FUZZY_COLOR.
+    #[arg(long, value_name = "NAME")]
+    color: Option<String>,
+
+    /// Print all matching branches, best first, without checking anything out
+    #[arg(short = 'l', long)]
+    list: bool,
+
+    /// With --list or --print, end each name with a NUL byte instead of a newline, like git's -z, and print names without decoration or color
+    #[arg(short = 'z', long)]
+    null: bool,
+
+    /// Print all matching branches as stable, tab-separated kind, name and score lines
+    #[arg(long, overrides_with_all = ["list", "json", "print"])]
+    porcelain: bool,
+
+    /// Also match local branches by their descriptions, as set by `git branch --edit-description`
+    #[arg(long)]
+    search_descriptions: bool,
+
+    /// Print a table of the matching branches with the kind of match that caught each one and what its score is made of, instead of checking anything out
+    #[arg(long, overrides_with_all = ["list", "json", "porcelain", "print"])]
+    explain: bool,
+
+    /// Print the number of matching branches, exiting with 2 if there are none
+    #[arg(long, overrides_with_all = ["list", "json", "porcelain", "print"])]
+    count: bool,
+
+    /// Print all matching branches as a JSON array, best first, without checking anything out
+    #[arg(long, overrides_with = "list")]
+    json: bool,
+
+    /// Print just the name of the single resolved branch, or the commit, without checking it out. Ambiguous matches are listed on stderr and exit non-zero.
+    #[arg(long, overrides_with_all = ["list", "json", "create", "create_from", "worktree"])]
+    print: bool,
+
+    /// Discard local changes when checking out
+    #[arg(short = 'f', long)]
+    force: bool,
+
+    /// Always use `git checkout`, even if `git switch` is available
+    #[arg(long)]
+    use_checkout: bool,
+
+    /// Stash uncommitted changes before checking out
+    #[arg(long, overrides_with = "force")]
+    autostash: bool,
+
+    /// Show at most N matches when listing an ambiguous pattern
+    #[arg(long, value_name = "N")]
+    max: Option<NonZeroUsize>,
+
+    /// Fetch from all remotes, or only from REMOTE with --fetch=REMOTE, before matching
+    #[arg(long, value_name = "REMOTE", num_args = 0..=1, require_equals = true, default_missing_value = "")]
+    fetch: Option<String>,
+
+    /// Also consider tags, which are checked out in detached HEAD
+    #[arg(long, global = true)]
+    tags: bool,
+
+    /// Show what would be checked out, deleted or merged without doing it
+    #[arg(short = 'n', long, global = true)]
+    dry_run: bool,
+
+    /// Don't ask for confirmation, e.g. before checking out a commit
+    #[arg(short = 'y', long, global = true)]
+    yes: bool,
+
+    /// Maximum edit distance allowed by the fuzzy matchers. 0 turns fuzzy matching off, leaving only exact, prefix and substring matches. Lenient thresholds produce closely scored matches, so they are listed rather than checked out. Defaults to a limit based on the pattern length.
+    #[arg(long, value_name = "N")]
+    threshold: Option<usize>,
+
+    /// Run as if started in this directory, like `git -C`
+    #[arg(short = 'C', long = "dir", value_name = "PATH")]
+    dir: Option<PathBuf>,
+
+    /// Give up on any git query, like a fetch, that takes longer than SECONDS. Checkouts aren't limited.
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<NonZeroU64>,
+
+    /// Print a completion script for bash, zsh, fish, elvish or powershell and exit
+    #[arg(long, value_name = "SHELL", hide = true)]
+    completions: Option<Shell>,
+
+    /// Extra arguments after `--`, passed verbatim to git checkout or switch
+    #[arg(last = true, value_name = "GIT_ARGS")]
+    git_args: Vec<String>,
 }
 
-#[derive(Debug, Clone)]
-struct Branch {
-    name: String,
+#[derive(Subcommand)]
+enum Commands {
+    /// Check out the branch that the patterns match, as without a subcommand. This is how to match a pattern that's also a subcommand's name, like `log`.
+    Checkout {
+        /// Branch name or pattern to match, and any further patterns that must also appear in the branch name
+        #[arg(value_name = "PATTERN", required = true)]
+        patterns: Vec<String>,
+
+        /// Extra arguments after `--`, passed verbatim to git checkout or switch
+        #[arg(last = true, value_name = "GIT_ARGS")]
+        git_args: Vec<String>,
+    },
+    /// Check that git and the repository are set up for git-fuzzy, and report what it sees
+    Doctor,
+    /// Delete the local branch that PATTERN matches, like `git branch -d`
+    Delete {
+        /// The pattern to match local branch names against, like when checking out
+        pattern: String,
+
+        /// Delete the branch even if it isn't merged, like `git branch -D`
+        #[arg(short = 'f', long)]
+        force: bool,
+    },
+    /// Diff against the branch that PATTERN matches, like `git diff`
+    Diff {
+        /// The pattern to match branch names against, like when checking out
+        pattern: String,
+
+        /// Extra arguments for git diff, e.g. `-- --stat` or `-- -- src/`
+        #[arg(last = true, value_name = "GIT_ARGS")]
+        git_args: Vec<String>,
+    },
+    /// Show the history of the branch that PATTERN matches, like `git log`
+    Log {
+        /// The pattern to match branch names against, like when checking out
+        pattern: String,
+
+        /// Extra arguments for git log, e.g. `-- --oneline` or `-- -n 5`
+        #[arg(last = true, value_name = "GIT_ARGS")]
+        git_args: Vec<String>,
+    },
+    /// Merge the branch that PATTERN matches into the current branch, like `git merge`
+    Merge {
+        /// The pattern to match branch names against, like when checking out
+        pattern: String,
+
+        /// Extra arguments for git merge, e.g. `-- --no-ff` or `-- --squash`
+        #[arg(last = true, value_name = "GIT_ARGS")]
+        git_args: Vec<String>,
+    },
+    /// Add a global `git fuzzy` alias that runs git-fuzzy
+    InstallAlias {
+        /// Print the git config command instead of running it
+        #[arg(long)]
+        print_only: bool,
+
+        /// Replace an existing `fuzzy` alias that runs something else
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+/// What to do when no branch matches the pattern
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum NoMatch {
+    Error,
+    Commit,
+    Create,
+}
+
+impl NoMatch {
+    /// The action that `--on-no-match` and its shorthands ask for. They
+    /// override each other, so at most one of them is still set and the
+    /// last one given wins. `--print` never changes anything, so with it
+    /// there's no branch to create.
+    fn from_cli(cli: &Cli) -> Self {
+        let action = if cli.create || cli.create_from.is_some() {
+            NoMatch::Create
+        } else if cli.no_commit_fallback {
+            NoMatch::Error
+        } else {
+            match cli.on_no_match.as_str() {
+                "error" => NoMatch::Error,
+                "create" => NoMatch::Create,
+                _ => NoMatch::Commit,
+            }
+        };
+        if cli.print && action == NoMatch::Create {
+            NoMatch::Error
+        } else {
+            action
+        }
+    }
+}
+
+/// Exit codes, which are kept stable for scripts
+const EXIT_SUCCESS: i32 = 0;
+/// Also used for usage errors and cancelled prompts
+const EXIT_FAILURE: i32 = 1;
+const EXIT_NOT_FOUND: i32 = 2;
+const EXIT_AMBIGUOUS: i32 = 3;
+const EXIT_GIT_FAILED: i32 = 4;
+const EXIT_GIT_NOT_FOUND: i32 = 5;
+
+/// The exit code to report an error with
+fn exit_code(error: &GitFuzzyError) -> i32 {
+    match error {
+        GitFuzzyError::NotFound(_) => EXIT_NOT_FOUND,
+        GitFuzzyError::Ambiguous { .. } => EXIT_AMBIGUOUS,
+        GitFuzzyError::GitNotFound => EXIT_GIT_NOT_FOUND,
+        GitFuzzyError::NotARepo { .. }
+        | GitFuzzyError::GitSpawn(_)
+        | GitFuzzyError::GitTimeout { .. }
+        | GitFuzzyError::GitCommand { .. } => EXIT_GIT_FAILED,
+        GitFuzzyError::InvalidPattern(_) | GitFuzzyError::Other(_) => EXIT_FAILURE,
+    }
+}
+
+/// A matched branch and its score, as emitted by `--json`
+#[derive(Serialize)]
+struct JsonMatch<'a> {
+    name: &'a str,
+    kind: RefKind,
     is_remote: bool,
+    committer_date: i64,
+    score: u32,
 }
 
-impl Branch {
-    fn new(name: String, is_remote: bool) -> Self {
-        Branch { name, is_remote }
+/// The command line arguments, with the whitespace-separated defaults from
+/// `GIT_FUZZY_OPTS` inserted before them. Later flags override earlier ones,
+/// including the flags they can't be combined with, like `--fzf` for
+/// `--picker`, so arguments given on the command line win.
+fn args_with_env_defaults() -> Vec<OsString> {
+    let mut args: Vec<OsString> = env::args_os().collect();
+    if let Some(opts) = env::var_os("GIT_FUZZY_OPTS") {
+        let defaults = opts
+            .to_string_lossy()
+            .split_whitespace()
+            .map(OsString::from)
+            .collect::<Vec<_>>();
+        let program_len = args.len().min(1);
+        args.splice(program_len..program_len, defaults);
     }
+    args
 }
 
-/// Find the git directory by walking up the filesystem
-fn find_git_directory() -> Option<PathBuf> {
-    let mut current_dir = env::current_dir().ok()?;
+/// Decide whether to colorize output. Colors are off with `--no-color` or
+/// `NO_COLOR`, and when stdout or stderr isn't a terminal unless
+/// `CLICOLOR_FORCE` is set.
+fn use_color(no_color: bool) -> bool {
+    let env_set = |name: &str| env::var_os(name).is_some_and(|v| !v.is_empty() && v != "0");
+    if no_color || env_set("NO_COLOR") {
+        return false;
+    }
+    if env_set("CLICOLOR_FORCE") {
+        return true;
+    }
+    io::stdout().is_terminal() && io::stderr().is_terminal()
+}
 
-    loop {
-        let git_path = current_dir.join(".git");
-        if git_path.exists() {
-            return Some(current_dir);
-        }
+/// The color to highlight matches in, from `--color` or else
+/// `GIT_FUZZY_COLOR`. An unknown name is warned about and green used instead.
+fn highlight_color(name: Option<String>) -> Color {
+    let Some(name) = name.or_else(|| env::var("GIT_FUZZY_COLOR").ok().filter(|c| !c.is_empty()))
+    else {
+        return Color::Green;
+    };
+    name.parse().unwrap_or_else(|_| {
+        eprintln!(
+            "{} Unknown color '{}', using green",
+            "Warning:".yellow().bold(),
+            name
+        );
+        Color::Green
+    })
+}
 
-        if !current_dir.pop() {
-            return None;
+/// Print a name for scripts, ended with a NUL byte for `--null` or else a
+/// newline
+fn print_entry(name: &str, null: bool) {
+    if null {
+        print!("{}\0", name);
+    } else {
+        println!("{}", name);
+    }
+}
+
+/// Deal with uncommitted changes before a checkout: stash them with
+/// `--autostash`, otherwise warn about them. Git decides whether they can be
+/// carried over, so the warning doesn't block the checkout.
+fn prepare_working_tree(git: &dyn GitRunner, cli: &Cli) -> Result<(), GitFuzzyError> {
+    if cli.force || !working_tree_is_dirty(git) {
+        return Ok(());
+    }
+
+    if cli.autostash {
+        stash_changes(git)?;
+        if !cli.quiet {
+            eprintln!("Stashed uncommitted changes; restore them with 'git stash pop'");
         }
+    } else if !cli.quiet {
+        eprintln!(
+            "{} You have uncommitted changes in your working tree",
+            "Warning:".yellow().bold()
+        );
+    }
+    Ok(())
+}
+
+/// Resolve the base of `--create-from` to a single branch, like the pattern
+/// but without asking, or failing that to a commit
+fn resolve_base(
+    git: &dyn GitRunner,
+    cli: &Cli,
+    branches: &[Branch],
+    base: &str,
+) -> Result<String, GitFuzzyError> {
+    let ignore_case = use_ignore_case(base, cli.ignore_case);
+    let ranked = Matcher::Fuzzy.find_matches(branches, base, ignore_case, cli.threshold);
+    let ranked = prefer_local_branches(git, ranked);
+    if ranked.is_empty() {
+        return match resolve_commit(git, base) {
+            Some(_) => Ok(base.to_string()),
+            None => Err(GitFuzzyError::NotFound(format!(
+                "No branch or commit matches the base '{}'",
+                base
+            ))),
+        };
+    }
+    match pick_unique(&ranked, cli.min_margin) {
+        Some(branch) => Ok(branch.name.clone()),
+        None => Err(GitFuzzyError::Ambiguous {
+            pattern: base.to_string(),
+            matches: ranked.iter().map(|(b, _)| b.name.clone()).collect(),
+        }),
     }
 }
 
-/// Execute a git command and return its output
-fn run_git_command(args: &[&str]) -> Result<String, String> {
-    let output = Command::new("git")
-        .args(args)
-        .output()
-        .map_err(|e| format!("Failed to execute git: {}", e))?;
+/// Find the git directory, reporting `-C`'s directory if it isn't in a
+/// repository
+fn find_repository(git: &dyn GitRunner, cli: &Cli) -> Result<PathBuf, GitFuzzyError> {
+    find_git_directory(git).map_err(|e| match e {
+        GitFuzzyError::NotARepo { .. } => GitFuzzyError::NotARepo {
+            dir: cli.dir.clone(),
+        },
+        e => e,
+    })
+}
+
+/// Branch-type prefixes ignored by `--smart-prefix`
+const COMMON_BRANCH_PREFIXES: &[&str] = &[
+    "feature/", "feat/", "bugfix/", "fix/", "hotfix/", "release/", "chore/",
+];
 
-    if !output.status.success() {
-        return Err(String::from_utf8_lossy(&output.stderr).to_string());
+/// Match like `--regex`, `--glob` or `--exact` say, or else fuzzily
+fn build_matcher(cli: &Cli, needle: &str, ignore_case: bool) -> Result<Matcher, GitFuzzyError> {
+    if cli.regex {
+        Matcher::regex(needle, ignore_case)
+    } else if cli.glob {
+        Matcher::glob(needle, ignore_case)
+    } else if cli.exact {
+        Ok(Matcher::Exact)
+    } else {
+        Ok(Matcher::anchored(needle).unwrap_or(Matcher::Fuzzy))
     }
+}
 
-    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+/// Pick between `git switch` and `git checkout`, unless `--use-checkout` forces the latter
+fn checkout_command(cli: &Cli, git: &dyn GitRunner) -> CheckoutCommand {
+    if cli.use_checkout {
+        CheckoutCommand::Checkout
+    } else {
+        CheckoutCommand::detect(git)
+    }
 }
 
-/// Get all git remotes
-fn get_git_remotes() -> Vec<String> {
-    run_git_command(&["remote"])
-        .unwrap_or_default()
-        .lines()
-        .map(|s| s.to_string())
-        .collect()
+/// Read the patterns from the first line of stdin, separated by whitespace
+fn read_patterns_from_stdin() -> Result<Vec<String>, GitFuzzyError> {
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line).map_err(|e| {
+        GitFuzzyError::Other(format!("Failed to read the pattern from stdin: {}", e))
+    })?;
+
+    let patterns: Vec<String> = line.split_whitespace().map(String::from).collect();
+    if patterns.is_empty() {
+        return Err(GitFuzzyError::Other(
+            "No pattern given on stdin".to_string(),
+        ));
+    }
+    Ok(patterns)
 }
 
-/// Get the current branch name
-fn get_current_branch() -> Option<String> {
-    run_git_command(&["branch", "--show-current"])
-        .ok()
-        .map(|s| s.trim().to_string())
+/// The width of the terminal, for fitting branch names on one line, or
+/// `None` when the output isn't shown in a terminal and shouldn't be cut
+fn terminal_width(on_terminal: bool) -> Option<usize> {
+    if !on_terminal {
+        return None;
+    }
+    terminal::size().ok().map(|(columns, _)| columns as usize)
 }
 
-/// Get all git refs (branches)
-fn get_git_refs(prefix: &str) -> Vec<String> {
-    let format_arg = "--format=%(refname:short)";
-    run_git_command(&["for-each-ref", format_arg, prefix])
-        .unwrap_or_default()
-        .lines()
-        .map(|s| s.to_string())
-        .collect()
+/// Fit a label into what's left of the terminal width after `prefix`
+/// characters of marker
+fn fit_label(label: String, width: Option<usize>, prefix: usize) -> String {
+    match width {
+        Some(width) => truncate_highlighted(&label, width.saturating_sub(prefix)),
+        None => label,
+    }
 }
 
-/// Get all branches (local and remote)
-fn get_all_branches() -> Vec<Branch> {
-    let mut branches = Vec::new();
+/// Ask the user a yes/no question, defaulting to no
+fn prompt_yes_no(question: &str) -> bool {
+    eprint!("{} [y/N] ", question);
+    if io::stderr().flush().is_err() {
+        return false;
+    }
 
-    // Get local branches
-    for branch in get_git_refs("refs/heads/") {
-        branches.push(Branch::new(branch, false));
+    let mut input = String::new();
+    match io::stdin().lock().read_line(&mut input) {
+        Ok(_) => matches!(input.trim().to_lowercase().as_str(), "y" | "yes"),
+        Err(_) => false,
     }
+}
 
-    // Get remote branches
-    let remotes = get_git_remotes();
-    for remote in remotes {
-        let prefix = format!("refs/remotes/{}/", remote);
-        for branch in get_git_refs(&prefix) {
-            branches.push(Branch::new(branch, true));
+/// Prompt the user to choose one of several matching branches by number.
+/// Returns `None` if stdin is closed before a valid choice is made.
+fn interactive_select(
+    git: &dyn GitRunner,
+    matches: &[Branch],
+    needle: &str,
+    max: Option<NonZeroUsize>,
+    highlight: impl Fn(&Branch) -> String,
+) -> Option<Branch> {
+    eprintln!("Ambiguous branch name '{}'. Multiple matches:", needle);
+    let (matches, hidden) = truncate_matches(matches, max);
+    let current_branch = get_current_branch(git);
+    let width = terminal_width(io::stderr().is_terminal());
+    for (i, branch) in matches.iter().enumerate() {
+        if let Some(heading) = group_heading(matches, i) {
+            eprintln!("{}", heading);
         }
+        let marker = if Some(&branch.name) == current_branch.as_ref() {
+            "*".green().bold().to_string()
+        } else {
+            " ".to_string()
+        };
+        let label = fit_label(highlight(branch), width, 6);
+        eprintln!("{} {:>2}) {}", marker, i + 1, label);
+    }
+    if hidden > 0 {
+        eprintln!("  ... and {} more", hidden);
     }
 
-    branches
+    let stdin = io::stdin();
+    loop {
+        eprint!("Select a branch [1-{}]: ", matches.len());
+        io::stderr().flush().ok()?;
+
+        let mut input = String::new();
+        if stdin.lock().read_line(&mut input).ok()? == 0 {
+            // EOF, e.g. Ctrl-D
+            eprintln!();
+            return None;
+        }
+
+        match input.trim().parse::<usize>() {
+            Ok(n) if (1..=matches.len()).contains(&n) => return Some(matches[n - 1].clone()),
+            _ => eprintln!("Please enter a number between 1 and {}", matches.len()),
+        }
+    }
 }
 
-/// Get tracking branches (local branches + remote branches without local counterparts)
-fn get_tracking_branches() -> Vec<Branch> {
-    let all_branches = get_all_branches();
-    let mut result = Vec::new();
+/// The heading to print before the match at `index`, when it starts a new
+/// group of matches of the same kind. Matches of a single kind aren't grouped.
+fn group_heading(matches: &[Branch], index: usize) -> Option<&'static str> {
+    let kind = matches[index].kind;
+    if matches.iter().all(|b| b.kind == kind) || index > 0 && matches[index - 1].kind == kind {
+        return None;
+    }
+
+    Some(match kind {
+        RefKind::Local => "Local branches:",
+        RefKind::Remote => "Remote branches:",
+        RefKind::Tag => "Tags:",
+        RefKind::Commit => "Commits:",
+    })
+}
 
-    // Get all local branch names (without remote prefix)
-    let local_branches: Vec<String> = all_branches
+/// Keep at most `max` matches, returning them along with how many were dropped
+fn truncate_matches(matches: &[Branch], max: Option<NonZeroUsize>) -> (&[Branch], usize) {
+    match max {
+        Some(max) if max.get() < matches.len() => {
+            (&matches[..max.get()], matches.len() - max.get())
+        }
+        _ => (matches, 0),
+    }
+}
+
+/// Print lines to stderr, through `$PAGER` if they won't fit in the terminal
+fn print_paged(lines: &[String]) {
+    let on_terminal = io::stdout().is_terminal() && io::stderr().is_terminal();
+    let fits = terminal::size().map_or(true, |(_, rows)| lines.len() < rows as usize);
+    if on_terminal && !fits {
+        if let Some(pager) = env::var("PAGER").ok().filter(|p| !p.is_empty()) {
+            if page(&pager, lines).is_ok() {
+                return;
+            }
+        }
+    }
+
+    for line in lines {
+        eprintln!("{}", line);
+    }
+}
+
+/// Show lines in a pager, which may include arguments like `less -R`
+fn page(pager: &str, lines: &[String]) -> io::Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(pager)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    {
+        let mut stdin = child.stdin.take().expect("pager stdin is piped");
+        for line in lines {
+            writeln!(stdin, "{}", line)?;
+        }
+    }
+    child.wait()?;
+    Ok(())
+}
+
+/// Let the user choose one of several matching branches with `fzf`.
+/// Returns `None` if the user cancels the selection.
+fn select_with_fzf(matches: &[Branch], needle: &str) -> Result<Option<Branch>, GitFuzzyError> {
+    let mut child = Command::new("fzf")
+        .arg("--no-multi")
+        .arg(format!("--prompt={}> ", needle))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| GitFuzzyError::Other(format!("Failed to execute fzf: {}", e)))?;
+
+    // Send plain labels, since fzf would display color codes literally
+    {
+        let mut stdin = child.stdin.take().expect("fzf stdin is piped");
+        for branch in matches {
+            writeln!(stdin, "{}", branch.display_name())
+                .map_err(|e| GitFuzzyError::Other(format!("Failed to write to fzf: {}", e)))?;
+        }
+    }
+
+    let output = child
+        .wait_with_output()
+        .map_err(|e| GitFuzzyError::Other(format!("Failed to read from fzf: {}", e)))?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    let selected = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok(matches
         .iter()
-        .filter(|b| !b.is_remote)
-        .map(|b| b.name.clone())
-        .collect();
+        .find(|b| b.display_name() == selected)
+        .cloned())
+}
 
-    for branch in all_branches {
-        if !branch.is_remote {
-            // Include all local branches
-            result.push(branch);
-        } else {
-            // For remote branches, only include if there's no corresponding local branch
-            let remote_name = &branch.name;
-            // Remote branches are in format "remote/branch-name"
-            if let Some(idx) = remote_name.find('/') {
-                let branch_name = &remote_name[idx + 1..];
-                if !local_branches.contains(&branch_name.to_string()) {
-                    result.push(branch);
+/// Restores the terminal when the picker exits, however it exits
+struct RawModeGuard;
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+        let _ = execute!(io::stderr(), cursor::Show);
+    }
+}
+
+/// Let the user choose one of several matching branches with the arrow keys.
+/// Returns `None` if the user cancels with Esc or Ctrl-C.
+fn pick_branch(
+    matches: &[Branch],
+    needle: &str,
+    highlight: impl Fn(&Branch) -> String,
+) -> io::Result<Option<Branch>> {
+    let mut stderr = io::stderr();
+    eprintln!(
+        "Ambiguous branch name '{}'. Select a branch (up/down, Enter to check out, Esc to cancel):",
+        needle
+    );
+
+    // Leave room for the header and the prompt line
+    let (columns, rows) = terminal::size()?;
+    let visible = matches.len().min((rows as usize).saturating_sub(2).max(1));
+
+    terminal::enable_raw_mode()?;
+    let _guard = RawModeGuard;
+    execute!(stderr, cursor::Hide)?;
+
+    let mut selected = 0;
+    let mut offset = 0;
+    loop {
+        // Scroll so the selected branch stays on screen
+        if selected < offset {
+            offset = selected;
+        } else if selected >= offset + visible {
+            offset = selected + 1 - visible;
+        }
+
+        for (i, branch) in matches.iter().enumerate().skip(offset).take(visible) {
+            let pointer = if i == selected {
+                ">".green().bold().to_string()
+            } else {
+                " ".to_string()
+            };
+            queue!(stderr, terminal::Clear(ClearType::CurrentLine))?;
+            let label = fit_label(highlight(branch), Some(columns as usize), 2);
+            write!(stderr, "{} {}\r\n", pointer, label)?;
+        }
+        stderr.flush()?;
+
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Up | KeyCode::Char('k') => selected = selected.saturating_sub(1),
+                KeyCode::Down | KeyCode::Char('j') => {
+                    selected = (selected + 1).min(matches.len() - 1)
+                }
+                KeyCode::Enter => return Ok(Some(matches[selected].clone())),
+                KeyCode::Esc => return Ok(None),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(None)
                 }
+                _ => {}
             }
         }
+
+        // Move back up to redraw the list in place
+        queue!(stderr, cursor::MoveUp(visible as u16))?;
+    }
+}
+
+fn main() {
+    // Parse command line arguments. Clap exits with 2 for usage errors, which
+    // would read as "not found", so report them as a general failure.
+    let mut cli = Cli::try_parse_from(args_with_env_defaults()).unwrap_or_else(|e| {
+        let _ = e.print();
+        exit(if e.use_stderr() {
+            EXIT_FAILURE
+        } else {
+            EXIT_SUCCESS
+        });
+    });
+    // `checkout` only spells out the default action, so it runs like no
+    // subcommand at all
+    if let Some(Commands::Checkout { patterns, git_args }) = &mut cli.command {
+        cli.patterns = std::mem::take(patterns);
+        cli.git_args.append(git_args);
+    }
+    set_verbose(cli.verbose);
+    colored::control::set_override(use_color(cli.no_color || cli.null));
+    set_highlight_color(highlight_color(cli.color.clone()));
+
+    if let Some(shell) = cli.completions {
+        clap_complete::generate(shell, &mut Cli::command(), "git-fuzzy", &mut io::stdout());
+        return;
     }
 
-    result
+    let git = open_git(SystemGit {
+        dir: cli.dir.clone(),
+        timeout: cli.timeout.map(|secs| Duration::from_secs(secs.get())),
+    });
+    let result = match &cli.command {
+        Some(Commands::Doctor) => Ok(doctor(git.as_ref(), &cli)),
+        Some(Commands::Delete { pattern, force }) => delete(git.as_ref(), &cli, pattern, *force),
+        Some(Commands::Diff { pattern, git_args }) => diff(git.as_ref(), &cli, pattern, git_args),
+        Some(Commands::Log { pattern, git_args }) => log(git.as_ref(), &cli, pattern, git_args),
+        Some(Commands::Merge { pattern, git_args }) => merge(git.as_ref(), &cli, pattern, git_args),
+        Some(Commands::InstallAlias { print_only, force }) => {
+            install_alias(git.as_ref(), *print_only, *force)
+        }
+        Some(Commands::Checkout { .. }) | None => run(&cli, git.as_ref()),
+    };
+    match result {
+        Ok(code) => exit(code),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            exit(exit_code(&e));
+        }
+    }
 }
 
-/// Match branches exactly by name
-fn match_branch_exactly(branches: &[Branch], needle: &str) -> Vec<Branch> {
-    branches
-        .iter()
-        .filter(|b| b.name == needle)
-        .cloned()
-        .collect()
+/// The alias that `install-alias` adds, and what it runs
+const ALIAS_NAME: &str = "fuzzy";
+const ALIAS_COMMAND: &str = "!git-fuzzy";
+
+/// Get a git alias from the global config, e.g. `!git-fuzzy` for `fuzzy`
+fn get_global_alias(git: &dyn GitRunner, name: &str) -> Option<String> {
+    git.run(&["config", "--global", "--get", &format!("alias.{}", name)])
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
 }
 
-/// Match branches by substring
-fn match_branch_substring(branches: &[Branch], needle: &str) -> Vec<Branch> {
-    branches
-        .iter()
-        .filter(|b| b.name.contains(needle))
-        .cloned()
-        .collect()
+/// Set a git alias in the global config, replacing any it already has
+fn set_global_alias(git: &dyn GitRunner, name: &str, value: &str) -> Result<(), GitFuzzyError> {
+    git.run(&["config", "--global", &format!("alias.{}", name), value])
+        .map(|_| ())
 }
 
-/// Checkout a branch
-fn checkout_branch(branch: &Branch) -> Result<(), String> {
-    let mut cmd = Command::new("git");
-    cmd.arg("checkout");
+/// Add the `git fuzzy` alias, leaving a different alias of the same name
+/// alone unless forced to replace it
+fn install_alias(git: &dyn GitRunner, print_only: bool, force: bool) -> Result<i32, GitFuzzyError> {
+    match get_global_alias(git, ALIAS_NAME) {
+        Some(existing) if existing == ALIAS_COMMAND => {
+            println!(
+                "The alias is already installed, run it as `git {}`",
+                ALIAS_NAME
+            );
+            return Ok(EXIT_SUCCESS);
+        }
+        Some(existing) if !force => {
+            return Err(GitFuzzyError::Other(format!(
+                "alias.{} is already set to '{}'; use --force to replace it",
+                ALIAS_NAME, existing
+            )));
+        }
+        _ => {}
+    }
+
+    if print_only {
+        println!(
+            "git config --global alias.{} '{}'",
+            ALIAS_NAME, ALIAS_COMMAND
+        );
+        return Ok(EXIT_SUCCESS);
+    }
+
+    set_global_alias(git, ALIAS_NAME, ALIAS_COMMAND)?;
+    println!("Installed the alias, run it as `git {}`", ALIAS_NAME);
+    Ok(EXIT_SUCCESS)
+}
 
-    // If it's a remote branch, create a local tracking branch
-    if branch.is_remote {
-        cmd.arg("--track");
+/// Match `pattern` against `branches` like checking out does, for the
+/// subcommands that need exactly one branch and don't offer a choice
+fn resolve_branch(
+    git: &dyn GitRunner,
+    cli: &Cli,
+    pattern: &str,
+    branches: &[Branch],
+) -> Result<Branch, GitFuzzyError> {
+    let ignore_case = use_ignore_case(pattern, cli.ignore_case);
+    let matcher = build_matcher(cli, pattern, ignore_case)?;
+    let branches = exclude_substrings(branches, &cli.not, ignore_case);
+    let ranked = matcher.find_matches(&branches, pattern, ignore_case, cli.threshold);
+    let ranked = prefer_local_branches(git, ranked);
+    if ranked.is_empty() {
+        return Err(GitFuzzyError::NotFound(format!(
+            "No branch matches '{}'",
+            pattern
+        )));
     }
+    match pick_unique(&ranked, cli.min_margin) {
+        Some(branch) => Ok(branch.clone()),
+        None => Err(GitFuzzyError::Ambiguous {
+            pattern: pattern.to_string(),
+            matches: ranked.iter().map(|(b, _)| b.name.clone()).collect(),
+        }),
+    }
+}
 
-    cmd.arg(&branch.name);
+/// Merge the one branch that `pattern` matches into the current branch
+fn merge(
+    git: &dyn GitRunner,
+    cli: &Cli,
+    pattern: &str,
+    git_args: &[String],
+) -> Result<i32, GitFuzzyError> {
+    find_repository(git, cli)?;
 
-    let status = cmd
-        .status()
-        .map_err(|e| format!("Failed to execute git checkout: {}", e))?;
+    // Merging the current branch into itself does nothing, so it's never
+    // a candidate
+    let Some(current_branch) = get_current_branch(git).filter(|b| !b.is_empty()) else {
+        return Err(GitFuzzyError::Other(
+            "Cannot merge without a current branch to merge into".to_string(),
+        ));
+    };
+    let branches: Vec<Branch> = get_refs(git, &["refs/heads/", "refs/remotes/"])?
+        .into_iter()
+        .filter(|b| !(b.is_local() && b.name == current_branch))
+        .collect();
+    let branch = resolve_branch(git, cli, pattern, &branches)?;
 
-    if !status.success() {
-        return Err(format!("git checkout failed for branch: {}", branch.name));
+    if cli.dry_run {
+        println!(
+            "Would merge {} branch '{}' into '{}'",
+            branch.kind.as_str(),
+            branch.name,
+            current_branch
+        );
+        return Ok(EXIT_SUCCESS);
     }
+    merge_branch(git, &branch.name, git_args)?;
+    Ok(EXIT_SUCCESS)
+}
 
-    Ok(())
+/// Get local and remote branches, and tags with `--tags`, for the
+/// subcommands that only read a branch
+fn get_branches_and_tags(git: &dyn GitRunner, cli: &Cli) -> Result<Vec<Branch>, GitFuzzyError> {
+    let mut ref_prefixes = vec!["refs/heads/", "refs/remotes/"];
+    if cli.tags {
+        ref_prefixes.push("refs/tags/");
+    }
+    get_refs(git, &ref_prefixes)
 }
 
-/// Checkout a commit
-fn checkout_commit(commit: &str) -> Result<(), String> {
-    let status = Command::new("git")
-        .arg("checkout")
-        .arg(commit)
-        .status()
-        .map_err(|e| format!("Failed to execute git checkout: {}", e))?;
+/// Show the log of the one branch that `pattern` matches
+fn log(
+    git: &dyn GitRunner,
+    cli: &Cli,
+    pattern: &str,
+    git_args: &[String],
+) -> Result<i32, GitFuzzyError> {
+    find_repository(git, cli)?;
+    let branch = resolve_branch(git, cli, pattern, &get_branches_and_tags(git, cli)?)?;
 
-    if !status.success() {
-        return Err(format!("git checkout failed for commit: {}", commit));
+    if cli.dry_run {
+        println!(
+            "Would show the log of {} branch '{}'",
+            branch.kind.as_str(),
+            branch.name
+        );
+        return Ok(EXIT_SUCCESS);
     }
+    show_log(git, &branch.name, git_args)?;
+    Ok(EXIT_SUCCESS)
+}
 
-    Ok(())
+/// Diff against the one branch that `pattern` matches
+fn diff(
+    git: &dyn GitRunner,
+    cli: &Cli,
+    pattern: &str,
+    git_args: &[String],
+) -> Result<i32, GitFuzzyError> {
+    find_repository(git, cli)?;
+    let branch = resolve_branch(git, cli, pattern, &get_branches_and_tags(git, cli)?)?;
+
+    if cli.dry_run {
+        println!(
+            "Would diff against {} branch '{}'",
+            branch.kind.as_str(),
+            branch.name
+        );
+        return Ok(EXIT_SUCCESS);
+    }
+    diff_against(git, &branch.name, git_args)?;
+    Ok(EXIT_SUCCESS)
 }
 
-/// Highlight the matched substring in a branch name
-fn highlight_match(branch_name: &str, needle: &str) -> String {
-    if let Some(pos) = branch_name.find(needle) {
-        let before = &branch_name[..pos];
-        let matched = &branch_name[pos..pos + needle.len()];
-        let after = &branch_name[pos + needle.len()..];
-        format!("{}{}{}", before, matched.green().bold(), after)
+/// Delete the one local branch that `pattern` matches, asking first in a
+/// terminal
+fn delete(
+    git: &dyn GitRunner,
+    cli: &Cli,
+    pattern: &str,
+    force: bool,
+) -> Result<i32, GitFuzzyError> {
+    find_repository(git, cli)?;
+
+    let branch = resolve_branch(git, cli, pattern, &get_local_branches(git)?)?;
+
+    // git refuses this too, but only after the prompt
+    if get_current_branch(git).as_ref() == Some(&branch.name) {
+        return Err(GitFuzzyError::Other(format!(
+            "Cannot delete the current branch '{}'",
+            branch.name
+        )));
+    }
+
+    if cli.dry_run {
+        println!("Would delete local branch '{}'", branch.name);
+        return Ok(EXIT_SUCCESS);
+    }
+    if io::stdout().is_terminal() && !cli.yes {
+        let question = format!("Delete local branch '{}'?", branch.name);
+        if !prompt_yes_no(&question) {
+            return Ok(EXIT_FAILURE);
+        }
+    }
+    delete_branch(git, &branch.name, force)?;
+    Ok(EXIT_SUCCESS)
+}
+
+/// Check the environment, printing a line for each check, and return the
+/// exit code: failure if anything would stop git-fuzzy from working
+fn doctor(git: &dyn GitRunner, cli: &Cli) -> i32 {
+    let ok = |message: String| println!("{} {}", "ok".green().bold(), message);
+    let warn = |message: String| println!("{} {}", "warning".yellow().bold(), message);
+    let fail = |message: String| println!("{} {}", "error".red().bold(), message);
+
+    match git.run(&["--version"]) {
+        Ok(version) => ok(format!("Found {}", version.trim())),
+        Err(e) => {
+            fail(e.to_string());
+            return EXIT_FAILURE;
+        }
+    }
+
+    if let Some(dir) = cli.dir.as_ref().filter(|dir| !dir.is_dir()) {
+        fail(format!(
+            "Cannot change to '{}': No such directory",
+            dir.display()
+        ));
+        return EXIT_FAILURE;
+    }
+    let git_dir = match find_git_directory(git) {
+        Ok(git_dir) => git_dir,
+        Err(_) => {
+            let error = GitFuzzyError::NotARepo {
+                dir: cli.dir.clone(),
+            };
+            fail(error.to_string());
+            return EXIT_FAILURE;
+        }
+    };
+    if is_bare_repository(git) {
+        ok(format!(
+            "In the bare repository {}, so matches are only reported, never checked out",
+            git_dir.display()
+        ));
+    } else if is_linked_worktree(git) {
+        ok(format!(
+            "In a linked worktree, whose git directory is {}",
+            git_dir.display()
+        ));
+    } else {
+        ok(format!("In the repository {}", git_dir.display()));
+    }
+
+    if is_detached_head(git) {
+        let sha = get_head_commit(git).unwrap_or_default();
+        warn(format!(
+            "HEAD is detached at {}, so there's no current branch",
+            sha
+        ));
     } else {
-        branch_name.to_string()
+        let branch = get_current_branch(git).unwrap_or_default();
+        ok(format!("On branch '{}'", branch));
     }
+
+    let (local, remote) = match (get_local_branches(git), get_remote_branches(git)) {
+        (Ok(local), Ok(remote)) => (local.len(), remote.len()),
+        (Err(e), _) | (_, Err(e)) => {
+            fail(format!("Cannot list branches: {}", e));
+            return EXIT_FAILURE;
+        }
+    };
+    let counts = format!("{} local and {} remote branches", local, remote);
+    if local + remote == 0 {
+        warn(format!("{}, so there's nothing to match yet", counts));
+    } else {
+        ok(counts);
+    }
+
+    if use_color(cli.no_color) {
+        ok("Color output is enabled".to_string());
+    } else {
+        ok(
+            "Color output is disabled, by --no-color, NO_COLOR or output that isn't a terminal"
+                .to_string(),
+        );
+    }
+
+    EXIT_SUCCESS
 }
 
-fn main() {
-    // Parse command line arguments
-    let cli = Cli::parse();
+/// Pick the git backend, reading refs with gitoxide when built with `gix`
+#[cfg(feature = "gix")]
+fn open_git(system: SystemGit) -> Box<dyn GitRunner> {
+    let fallback = SystemGit {
+        dir: system.dir.clone(),
+        timeout: system.timeout,
+    };
+    match git_fuzzy::GixGit::open(system) {
+        Ok(git) => Box::new(git),
+        // Let `run` report the missing repository as usual
+        Err(_) => Box::new(fallback),
+    }
+}
+
+/// Pick the git backend, reading refs with gitoxide when built with `gix`
+#[cfg(not(feature = "gix"))]
+fn open_git(system: SystemGit) -> Box<dyn GitRunner> {
+    Box::new(system)
+}
+
+/// Resolve the pattern and check out the result, returning the exit code
+fn run(cli: &Cli, git: &dyn GitRunner) -> Result<i32, GitFuzzyError> {
+    if let Some(dir) = cli.dir.as_ref().filter(|dir| !dir.is_dir()) {
+        return Err(GitFuzzyError::Other(format!(
+            "Cannot change to '{}': No such directory",
+            dir.display()
+        )));
+    }
 
     // Check if we're in a git repository
-    if find_git_directory().is_none() {
-        eprintln!("Error: Not in a git repository");
-        exit(1);
+    let git_dir = find_repository(git, cli)?;
+    log_verbose(&format!("Using git directory {}", git_dir.display()));
+    if cli.verbose && is_detached_head(git) {
+        let sha = get_head_commit(git).unwrap_or_default();
+        log_verbose(&format!("HEAD is detached at {}", sha));
+    }
+
+    // A bare repository has no working tree, so just report what would be
+    // checked out
+    let dry_run = cli.dry_run || is_bare_repository(git);
+    if dry_run && !cli.dry_run {
+        log_verbose("Bare repository, so nothing will be checked out");
+    }
+
+    // A pull request is looked up by its number rather than matched
+    if let Some(number) = cli.pr {
+        return check_out_pull_request(git, cli, number, dry_run);
     }
 
+    let patterns = if cli.stdin {
+        read_patterns_from_stdin()?
+    } else {
+        cli.patterns.clone()
+    };
+
     // If no pattern is provided, list all local branches alphabetically
-    if cli.pattern.is_none() {
-        let mut local_branches = get_git_refs("refs/heads/");
+    if patterns.is_empty() {
+        let mut local_branches = get_git_refs(git, "refs/heads/")?;
         local_branches.sort();
-        let current_branch = get_current_branch();
+        let current_branch = get_current_branch(git);
         for branch in local_branches {
-            if Some(&branch) == current_branch.as_ref() {
+            if cli.null {
+                print_entry(&branch, true);
+            } else if Some(&branch) == current_branch.as_ref() {
                 println!("{} {}", "*".green().bold(), branch.green().bold());
             } else {
                 println!("  {}", branch);
             }
         }
-        return;
+        return Ok(EXIT_SUCCESS);
+    }
+
+    // The first pattern is matched as usual, and any others must also
+    // appear in the branch name
+    let needle = &patterns[0];
+    let required = &patterns[1..];
+    let all_patterns = patterns.join(" ");
+
+    // Like git, '-' means the previously checked out branch
+    if needle == "-" && required.is_empty() {
+        let Some(previous) = get_previous_branch(git) else {
+            return Err(GitFuzzyError::NotFound(
+                "No previous branch to switch back to".to_string(),
+            ));
+        };
+        if cli.print {
+            print_entry(&previous, cli.null);
+            return Ok(EXIT_SUCCESS);
+        }
+        let args = checkout_previous_args(cli.force, &cli.git_args);
+        if dry_run {
+            println!("Would check out previous branch '{}'", previous);
+            print_dry_run_command(cli, &args);
+            return Ok(EXIT_SUCCESS);
+        }
+        prepare_working_tree(git, cli)?;
+        git.run_interactive(&args)?;
+        return Ok(EXIT_SUCCESS);
     }
+    let ignore_case = use_ignore_case(&all_patterns, cli.ignore_case);
 
-    let needle = cli.pattern.as_ref().unwrap();
+    // Refresh remote-tracking branches, but carry on with the refs we have
+    // if that fails, e.g. when offline
+    if cli.offline && cli.fetch.is_some() {
+        log_verbose("Skipping --fetch, since --offline is set");
+    } else if let Some(remote) = &cli.fetch {
+        let remote = Some(remote.as_str()).filter(|r| !r.is_empty());
+        if let Err(e) = fetch_remotes(git, remote) {
+            if !cli.quiet {
+                eprintln!("{} {}", "Warning:".yellow().bold(), e);
+            }
+        }
+    }
 
-    // Get all tracking branches
-    let branches = get_tracking_branches();
+    let matcher = build_matcher(cli, needle, ignore_case)?;
+
+    // Prefixes are stripped up to a slash, whether or not it was given
+    let mut prefixes: Vec<String> = cli
+        .strip_prefix
+        .iter()
+        .map(|prefix| format!("{}/", prefix.trim_end_matches('/')))
+        .collect();
+    if cli.smart_prefix {
+        prefixes.extend(COMMON_BRANCH_PREFIXES.iter().map(|p| p.to_string()));
+    }
+
+    // The part of a branch name that the pattern is matched against, which
+    // always ends the name
+    let match_key = |branch: &Branch| -> String {
+        if cli.basename {
+            branch_basename(&branch.name).to_string()
+        } else {
+            strip_branch_prefix(branch, &prefixes).to_string()
+        }
+    };
+    let find_name_matches = |branches: &[Branch]| {
+        if cli.basename {
+            matcher.find_matches_by(branches, needle, ignore_case, cli.threshold, |b| {
+                branch_basename(&b.name)
+            })
+        } else if !prefixes.is_empty() {
+            matcher.find_matches_by(branches, needle, ignore_case, cli.threshold, |b| {
+                strip_branch_prefix(b, &prefixes)
+            })
+        } else {
+            matcher.find_matches(branches, needle, ignore_case, cli.threshold)
+        }
+    };
+    // With --search-descriptions, a local branch may also match by its
+    // description, scoring as well as its best match
+    let find_matches = |branches: &[Branch]| {
+        let mut ranked = find_name_matches(branches);
+        if !cli.search_descriptions {
+            return ranked;
+        }
+        // Only keep the descriptions that explain why a branch matched, so
+        // that listings point those out
+        for (branch, _) in &mut ranked {
+            branch.description = None;
+        }
+        let described: Vec<Branch> = branches
+            .iter()
+            .filter(|b| b.description.is_some())
+            .cloned()
+            .collect();
+        let by_description =
+            matcher.find_matches_by(&described, needle, ignore_case, cli.threshold, |b| {
+                b.description.as_deref().unwrap_or_default()
+            });
+        for (branch, score) in by_description {
+            match ranked.iter_mut().find(|(b, _)| b.name == branch.name) {
+                Some((_, best)) => *best = (*best).max(score),
+                None => ranked.push((branch, score)),
+            }
+        }
+        ranked.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+        ranked
+    };
+
+    // Get candidate branches, with remote branches if asked for and tags
+    // with --tags, all at once. Checking out a remote branch with
+    // --remote-only creates a local tracking branch for it.
+    let collect_candidates = |with_remotes: bool| {
+        let mut ref_prefixes = Vec::new();
+        if !cli.remote_only {
+            ref_prefixes.push("refs/heads/");
+        }
+        if with_remotes {
+            ref_prefixes.push("refs/remotes/");
+        }
+        if cli.tags {
+            ref_prefixes.push("refs/tags/");
+        }
+        let mut branches = get_refs(git, &ref_prefixes)?;
+        if cli.search_descriptions {
+            let mut descriptions = get_branch_descriptions(git);
+            for branch in branches.iter_mut().filter(|b| b.is_local()) {
+                branch.description = descriptions.remove(&branch.name);
+            }
+        }
+        if with_remotes && !cli.remote_only {
+            let preferred_remotes = if cli.prefer_remote.is_empty() {
+                get_default_remote(git).into_iter().collect()
+            } else {
+                cli.prefer_remote.clone()
+            };
+            branches = select_tracking_branches(git, branches, &preferred_remotes);
+        }
+
+        let count_kind = |kind| branches.iter().filter(|b| b.kind == kind).count();
+        log_verbose(&format!(
+            "Collected {} local branches, {} remote branches and {} tags",
+            count_kind(RefKind::Local),
+            count_kind(RefKind::Remote),
+            count_kind(RefKind::Tag)
+        ));
+        Ok::<_, GitFuzzyError>(branches)
+    };
+
+    let match_candidates = |branches: &[Branch]| {
+        // Only branches containing every further pattern, and none of the
+        // noise left out with --not, are matched at all
+        let wanted = require_substrings(branches, required, ignore_case);
+        let branches = &exclude_substrings(&wanted, &cli.not, ignore_case);
+        if branches.len() < wanted.len() {
+            log_verbose(&format!(
+                "Excluded {} branches with --not",
+                wanted.len() - branches.len()
+            ));
+        }
+
+        // Checking out the current branch again does nothing, so try
+        // matching without it first, but still find it when it's the only
+        // match
+        let mut ranked = Vec::new();
+        if cli.exclude_current {
+            if let Some(current_branch) = get_current_branch(git) {
+                let others: Vec<Branch> = branches
+                    .iter()
+                    .filter(|b| !(b.is_local() && b.name == current_branch))
+                    .cloned()
+                    .collect();
+                ranked = find_matches(&others);
+            }
+        }
+        if ranked.is_empty() {
+            ranked = find_matches(branches);
+        }
+
+        // A local branch beats its own remote-tracking branch
+        prefer_local_branches(git, ranked)
+    };
+
+    // Remotes can be slow to enumerate, so unless every match is wanted,
+    // they're only queried when no local branch matches for sure. A local
+    // branch that only matches fuzzily could be a typo of a remote branch's
+    // exact name.
+    let local_only = cli.local_only || cli.offline;
+    let lazy = !local_only
+        && !cli.remote_only
+        && !cli.all_refs
+        && !cli.list
+        && !cli.json
+        && !cli.porcelain
+        && !cli.count
+        && !cli.explain;
+    let mut branches = collect_candidates(!local_only && !lazy)?;
+    let mut ranked = match_candidates(&branches);
+    if lazy && !ranked.iter().any(|(_, score)| matcher.is_confident(*score)) {
+        log_verbose("No local branch matches for sure, so trying remote branches too");
+        branches = collect_candidates(true)?;
+        ranked = match_candidates(&branches);
+    }
+
+    // Counting only looks at branches, so a pattern that also looks like a
+    // commit hash isn't counted twice
+    if cli.count {
+        println!("{}", ranked.len());
+        return Ok(if ranked.is_empty() {
+            EXIT_NOT_FOUND
+        } else {
+            EXIT_SUCCESS
+        });
+    }
+
+    // Like counting, explaining is about the branches that matched
+    if cli.explain {
+        let max_distance = cli
+            .threshold
+            .unwrap_or_else(|| default_max_distance(needle));
+        let width = ranked
+            .iter()
+            .map(|(b, _)| b.name.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max("BRANCH".len());
+        let row = |name: &str, kind: &str, parts: [String; 5]| {
+            let [base, word, short, local, score] = parts;
+            println!(
+                "{:<width$}  {:<13}  {:>5}  {:>5}  {:>5}  {:>5}  {:>5}",
+                name, kind, base, word, short, local, score
+            );
+        };
+        row(
+            "BRANCH",
+            "MATCH",
+            ["BASE", "WORD", "SHORT", "LOCAL", "SCORE"].map(String::from),
+        );
+        for (branch, score) in &ranked {
+            let key = Branch::new(match_key(branch), branch.kind, branch.committer_date);
+            let breakdown = matches!(matcher, Matcher::Fuzzy)
+                .then(|| explain_score(&key, needle, ignore_case, max_distance))
+                .flatten();
+            match breakdown {
+                Some(parts) => row(
+                    &branch.name,
+                    parts.kind,
+                    [
+                        parts.base,
+                        parts.word_boundary_bonus,
+                        parts.short_name_bonus,
+                        parts.local_bonus,
+                        *score,
+                    ]
+                    .map(|n| n.to_string()),
+                ),
+                // Other matchers, and matches by description, have no parts
+                None => {
+                    let kind = match &matcher {
+                        Matcher::Fuzzy | Matcher::Exact if branch.description.is_some() => {
+                            "description"
+                        }
+                        Matcher::Fuzzy | Matcher::Exact => "exact",
+                        Matcher::Regex(_) => "regex",
+                        Matcher::Glob(_) => "glob",
+                        Matcher::Anchored { .. } => "anchored",
+                    };
+                    let none = || "-".to_string();
+                    row(
+                        &branch.name,
+                        kind,
+                        [none(), none(), none(), none(), score.to_string()],
+                    );
+                }
+            }
+        }
+        return Ok(if ranked.is_empty() {
+            EXIT_NOT_FOUND
+        } else {
+            EXIT_SUCCESS
+        });
+    }
+
+    // Several patterns are highlighted wherever each of them appears, and
+    // a pattern matched against part of the name only within that part
+    let highlight_name = |branch: &Branch, name: &str| {
+        if !required.is_empty() {
+            return highlight_tokens(name, &patterns, ignore_case);
+        }
+        let key = match_key(branch);
+        match name.strip_suffix(key.as_str()) {
+            Some(unmatched) => format!(
+                "{}{}",
+                unmatched,
+                matcher.highlight(&key, needle, ignore_case)
+            ),
+            None => matcher.highlight(name, needle, ignore_case),
+        }
+    };
+
+    // A pattern that looks like a commit hash may mean the commit rather than
+    // a branch that happens to match, so offer both. When no branch matches,
+    // the commit fallback below handles it instead.
+    let no_match = NoMatch::from_cli(cli);
+    if matches!(matcher, Matcher::Fuzzy)
+        && no_match != NoMatch::Error
+        && required.is_empty()
+        && !ranked.is_empty()
+        && looks_like_hash(needle)
+    {
+        if let Some(sha) = resolve_commit(git, needle) {
+            log_verbose(&format!("'{}' also resolves to commit {}", needle, sha));
+            // Tie with the best branch so that neither wins automatically
+            let score = ranked[0].1;
+            ranked.push((Branch::new(needle.clone(), RefKind::Commit, 0), score));
+        }
+    }
+
+    // Emit the matches, best first, for other tools to consume
+    if cli.json {
+        let json_matches: Vec<JsonMatch> = ranked
+            .iter()
+            .map(|(branch, score)| JsonMatch {
+                name: &branch.name,
+                kind: branch.kind,
+                is_remote: branch.is_remote(),
+                committer_date: branch.committer_date,
+                score: *score,
+            })
+            .collect();
+        let json = serde_json::to_string_pretty(&json_matches)
+            .map_err(|e| GitFuzzyError::Other(format!("Failed to serialize matches: {}", e)))?;
+        println!("{}", json);
+        return Ok(EXIT_SUCCESS);
+    }
 
-    // Try exact match first
-    let mut matches = match_branch_exactly(&branches, needle);
+    // Just print the matches, best first. In a terminal, mark the current
+    // branch like `git branch` does, but keep piped output to bare names.
+    if cli.list && cli.null {
+        for (branch, _) in &ranked {
+            print_entry(&branch.name, true);
+        }
+        return Ok(EXIT_SUCCESS);
+    }
+    if cli.list {
+        let current_branch = io::stdout()
+            .is_terminal()
+            .then(|| get_current_branch(git))
+            .flatten();
+        let width = terminal_width(io::stdout().is_terminal());
+        let marker_width = if current_branch.is_some() { 2 } else { 0 };
+        for (branch, _) in &ranked {
+            let label = branch.label(highlight_name(branch, &branch.name));
+            let label = fit_label(label, width, marker_width);
+            if Some(&branch.name) == current_branch.as_ref() {
+                println!("{} {}", "*".green().bold(), label);
+            } else if current_branch.is_some() {
+                println!("  {}", label);
+            } else {
+                println!("{}", label);
+            }
+        }
+        return Ok(EXIT_SUCCESS);
+    }
 
-    // If no exact match, try substring match
-    if matches.is_empty() {
-        matches = match_branch_substring(&branches, needle);
+    // One record per match, in a format that mustn't change: new fields may
+    // only ever be added at the end
+    if cli.porcelain {
+        for (branch, score) in &ranked {
+            println!("{}\t{}\t{}", branch.kind.as_str(), branch.name, score);
+        }
+        return Ok(EXIT_SUCCESS);
     }
 
-    match matches.len() {
-        0 => {
+    let unique = pick_unique(&ranked, cli.min_margin).cloned();
+    let branch = match ranked.as_slice() {
+        [] if no_match == NoMatch::Create => {
+            // No branch matches, so create one with the pattern as its name
+            if !required.is_empty() {
+                return Err(GitFuzzyError::Other(
+                    "--create needs a single pattern to name the branch".to_string(),
+                ));
+            }
+            if cli.worktree.is_some() {
+                return Err(GitFuzzyError::Other(
+                    "--on-no-match create can't be combined with --worktree".to_string(),
+                ));
+            }
+            let base = match &cli.create_from {
+                Some(base) => Some(resolve_base(git, cli, &branches, base)?),
+                None => None,
+            };
+            let command = checkout_command(cli, git);
+            let args = create_branch_args(command, needle, base.as_deref(), &cli.git_args);
+            if dry_run {
+                match &base {
+                    Some(base) => println!("Would create branch '{}' from '{}'", needle, base),
+                    None => println!("Would create branch '{}'", needle),
+                }
+                print_dry_run_command(cli, &args);
+                return Ok(EXIT_SUCCESS);
+            }
+            git.run_interactive(&args)?;
+            return Ok(EXIT_SUCCESS);
+        }
+        // An anchored pattern means a branch name, and git would read a
+        // leading `^` as excluding a commit
+        [] if matches!(matcher, Matcher::Anchored { .. }) => {
+            return Err(GitFuzzyError::NotFound(format!(
+                "No branch matches '{}'",
+                all_patterns
+            )));
+        }
+        [] if matches!(matcher, Matcher::Exact) => {
+            return Err(GitFuzzyError::NotFound(format!(
+                "No branch named '{}'",
+                all_patterns
+            )));
+        }
+        [] if !required.is_empty() || no_match == NoMatch::Error => {
+            return Err(GitFuzzyError::NotFound(format!(
+                "No branch matches '{}'",
+                all_patterns
+            )));
+        }
+        [] => {
             // No branch matches, try to checkout as a commit
-            println!("No branches match '{}', trying as commit...", needle);
-            if let Err(e) = checkout_commit(needle) {
-                eprintln!("Error: {}", e);
-                exit(1);
+            let Some(sha) = resolve_commit(git, needle) else {
+                return Err(GitFuzzyError::NotFound(format!(
+                    "No branch or commit matches '{}'",
+                    needle
+                )));
+            };
+            if cli.print {
+                print_entry(&sha, cli.null);
+                return Ok(EXIT_SUCCESS);
+            }
+            let commit = Branch::new(needle.to_string(), RefKind::Commit, 0);
+            let command = checkout_command(cli, git);
+            let args = checkout_commit_args(command, needle, cli.force, &cli.git_args);
+            if dry_run {
+                if let Some(path) = &cli.worktree {
+                    return check_out_in_worktree(git, cli, path, &commit, true);
+                }
+                println!("Would check out commit '{}' ({})", needle, sha);
+                print_dry_run_command(cli, &args);
+                return Ok(EXIT_SUCCESS);
+            }
+
+            if io::stdout().is_terminal() && !cli.yes {
+                let question = format!("No branch matched '{}'; check out as commit?", needle);
+                if !prompt_yes_no(&question) {
+                    return Ok(EXIT_FAILURE);
+                }
+            } else if !cli.quiet {
+                println!("No branches match '{}', trying as commit...", needle);
+            }
+            if let Some(path) = &cli.worktree {
+                return check_out_in_worktree(git, cli, path, &commit, false);
             }
+            prepare_working_tree(git, cli)?;
+            git.run_interactive(&args)?;
+            print_detached_hint(git, cli);
+            return Ok(EXIT_SUCCESS);
         }
-        1 => {
-            // Exactly one match, checkout that branch
-            let branch = &matches[0];
-            if let Err(e) = checkout_branch(branch) {
-                eprintln!("Error: {}", e);
-                exit(1);
+        // Exactly one match, or one that clearly beats the rest, checkout
+        // that branch
+        _ if unique.is_some() => {
+            let branch = unique.expect("just checked");
+            if ranked.len() > 1 && !cli.quiet {
+                eprintln!("Auto-selected '{}' as the best match", branch.name);
             }
+            branch
         }
         _ => {
-            // Multiple matches, show them to the user
-            eprintln!("Ambiguous branch name '{}'. Multiple matches:", needle);
-            let current_branch = get_current_branch();
-            for branch in matches {
-                let highlighted = highlight_match(&branch.name, needle);
-                if Some(&branch.name) == current_branch.as_ref() {
-                    eprintln!("{} {}", "*".green().bold(), highlighted);
+            // Multiple comparable matches, let the user pick one if they can,
+            // grouped into local branches, remote branches, tags and commits.
+            // Each group offers the most recently checked out first, then the
+            // most recently committed to.
+            let recency = branch_recency(git);
+            let recent_first = |b: &Branch| {
+                let checked_out = match b.kind {
+                    RefKind::Local => recency.get(&b.name).copied(),
+                    _ => None,
+                };
+                (
+                    checked_out.unwrap_or(usize::MAX),
+                    std::cmp::Reverse(b.committer_date),
+                )
+            };
+            let mut candidates: Vec<Branch> = ranked.iter().map(|(b, _)| b.clone()).collect();
+            candidates.sort_by_key(|b| (b.kind, recent_first(b)));
+            let highlight =
+                |branch: &Branch| branch.display_label(highlight_name(branch, branch.short_name()));
+            let interactive = !dry_run && !cli.print;
+            let selection = if cli.first {
+                // Recency only breaks ties, so a worse match that happens to
+                // be checked out more recently never wins
+                let best_score = ranked.iter().map(|(_, score)| *score).max();
+                let best: Vec<&Branch> = ranked
+                    .iter()
+                    .filter(|(_, score)| Some(*score) == best_score)
+                    .map(|(b, _)| b)
+                    .collect();
+                let most_recent = best
+                    .iter()
+                    .min_by_key(|b| recent_first(b))
+                    .expect("there are several candidates");
+                if !cli.quiet {
+                    eprintln!(
+                        "Picked '{}', the most recently used of {} best matches",
+                        most_recent.name,
+                        best.len()
+                    );
+                }
+                Some((*most_recent).clone())
+            } else if interactive && cli.fzf {
+                select_with_fzf(&candidates, &all_patterns)?
+            } else if interactive && io::stdout().is_terminal() {
+                if cli.picker {
+                    pick_branch(&candidates, &all_patterns, highlight)
+                        .map_err(|e| GitFuzzyError::Other(format!("Branch picker failed: {}", e)))?
                 } else {
-                    eprintln!("  {}", highlighted);
+                    interactive_select(git, &candidates, &all_patterns, cli.max, highlight)
+                }
+            } else {
+                // Otherwise, show them to the user
+                let mut lines = vec![format!(
+                    "Ambiguous branch name '{}'. Multiple matches:",
+                    all_patterns
+                )];
+                let (shown, hidden) = truncate_matches(&candidates, cli.max);
+                let current_branch = get_current_branch(git);
+                let width = terminal_width(io::stderr().is_terminal());
+                for (i, branch) in shown.iter().enumerate() {
+                    if let Some(heading) = group_heading(shown, i) {
+                        lines.push(heading.to_string());
+                    }
+                    let highlighted = fit_label(highlight(branch), width, 2);
+                    if Some(&branch.name) == current_branch.as_ref() {
+                        lines.push(format!("{} {}", "*".green().bold(), highlighted));
+                    } else {
+                        lines.push(format!("  {}", highlighted));
+                    }
+                }
+                if hidden > 0 {
+                    lines.push(format!("  ... and {} more", hidden));
                 }
+                print_paged(&lines);
+                return Ok(EXIT_AMBIGUOUS);
+            };
+
+            let Some(branch) = selection else {
+                return Ok(EXIT_FAILURE);
+            };
+            branch
+        }
+    };
+
+    log_verbose(&format!(
+        "Resolved '{}' to {}",
+        all_patterns,
+        describe_branch(&branch)
+    ));
+
+    if cli.print {
+        print_entry(&branch.name, cli.null);
+        return Ok(EXIT_SUCCESS);
+    }
+
+    if let Some(path) = &cli.worktree {
+        return check_out_in_worktree(git, cli, path, &branch, dry_run);
+    }
+
+    if (cli.track || cli.set_upstream_to.is_some()) && branch.is_remote() {
+        // Create the local tracking branch explicitly, named after the
+        // remote branch without its remote
+        let remotes = get_git_remotes(git);
+        let Some((_, local_name)) = split_remote_branch(&branch.name, &remotes) else {
+            return Err(GitFuzzyError::Other(format!(
+                "Could not determine the remote of branch '{}'",
+                branch.name
+            )));
+        };
+
+        // Check the other upstream exists before creating anything, so a
+        // typo doesn't leave a half-configured branch behind
+        let upstream = match &cli.set_upstream_to {
+            Some(remote) => {
+                if !remotes.contains(remote) {
+                    return Err(GitFuzzyError::NotFound(format!(
+                        "No remote named '{}'",
+                        remote
+                    )));
+                }
+                let upstream = format!("{}/{}", remote, local_name);
+                let upstream_ref = format!("refs/remotes/{}", upstream);
+                if git
+                    .run(&["rev-parse", "--verify", "--quiet", &upstream_ref])
+                    .is_err()
+                {
+                    return Err(GitFuzzyError::NotFound(format!(
+                        "No remote branch '{}' to track",
+                        upstream
+                    )));
+                }
+                upstream
+            }
+            None => branch.name.clone(),
+        };
+
+        let args = create_tracking_branch_args(
+            checkout_command(cli, git),
+            local_name,
+            &branch.name,
+            cli.force,
+            &cli.git_args,
+        );
+        let upstream_args =
+            (upstream != branch.name).then(|| set_upstream_args(local_name, &upstream));
+        if dry_run {
+            println!(
+                "Would create local branch '{}' tracking '{}'",
+                local_name, upstream
+            );
+            print_dry_run_command(cli, &args);
+            if let Some(upstream_args) = &upstream_args {
+                print_dry_run_followup(cli, upstream_args);
+            }
+            if cli.pull {
+                print_dry_run_pull(git, cli, local_name, true);
             }
-            exit(1);
+            return Ok(EXIT_SUCCESS);
+        }
+        prepare_working_tree(git, cli)?;
+        git.run_interactive(&args)?;
+        if let Some(upstream_args) = &upstream_args {
+            git.run(upstream_args)?;
+        }
+        if let Some(actual) = get_upstream(git, local_name) {
+            log_verbose(&format!("Branch '{}' tracks '{}'", local_name, actual));
+        }
+        if cli.pull {
+            return Ok(pull_after_checkout(git, cli, local_name));
+        }
+        return Ok(EXIT_SUCCESS);
+    }
+
+    let command = checkout_command(cli, git);
+    let args = match branch.kind {
+        RefKind::Commit => checkout_commit_args(command, &branch.name, cli.force, &cli.git_args),
+        RefKind::Local | RefKind::Remote | RefKind::Tag => {
+            checkout_branch_args(command, &branch, cli.force, &cli.git_args)
+        }
+    };
+    let pull = cli.pull && matches!(branch.kind, RefKind::Local | RefKind::Remote);
+    if dry_run {
+        println!("Would check out {}", describe_branch(&branch));
+        print_dry_run_command(cli, &args);
+        if pull {
+            print_dry_run_pull(git, cli, &branch.name, branch.is_remote());
         }
+        return Ok(EXIT_SUCCESS);
+    }
+
+    prepare_working_tree(git, cli)?;
+    git.run_interactive(&args)?;
+    if branch.kind == RefKind::Commit {
+        print_detached_hint(git, cli);
+    }
+    if pull {
+        let checked_out = get_current_branch(git).unwrap_or_else(|| branch.name.clone());
+        return Ok(pull_after_checkout(git, cli, &checked_out));
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+/// The ref that a forge publishes a pull request's head under: GitHub's
+/// `refs/pull/N/head`, or GitLab's `refs/merge-requests/N/head`
+fn pull_request_ref(number: u64, gitlab: bool) -> String {
+    if gitlab {
+        format!("refs/merge-requests/{}/head", number)
+    } else {
+        format!("refs/pull/{}/head", number)
+    }
+}
+
+/// Check out a pull request's head, which the forge publishes under its own
+/// refs, or create a `pr-N` branch there with `--create`
+fn check_out_pull_request(
+    git: &dyn GitRunner,
+    cli: &Cli,
+    number: u64,
+    dry_run: bool,
+) -> Result<i32, GitFuzzyError> {
+    let pr_ref = pull_request_ref(number, cli.forge == "gitlab");
+    let Some(sha) = resolve_commit(git, &pr_ref) else {
+        let remote = get_default_remote(git).unwrap_or_else(|| "origin".to_string());
+        return Err(GitFuzzyError::NotFound(format!(
+            "No ref '{}' for pull request {}; fetch it first with `git fetch {} {}:{}`",
+            pr_ref, number, remote, pr_ref, pr_ref
+        )));
+    };
+    log_verbose(&format!("Pull request {} is at {}", number, sha));
+
+    if cli.print {
+        print_entry(&pr_ref, cli.null);
+        return Ok(EXIT_SUCCESS);
+    }
+    let branch_name = format!("pr-{}", number);
+    let command = checkout_command(cli, git);
+    let args = if cli.create {
+        create_branch_args(command, &branch_name, Some(&pr_ref), &cli.git_args)
+    } else {
+        checkout_commit_args(command, &pr_ref, cli.force, &cli.git_args)
+    };
+    if dry_run {
+        if cli.create {
+            println!("Would create branch '{}' at '{}'", branch_name, pr_ref);
+        } else {
+            println!("Would check out '{}' (in detached HEAD)", pr_ref);
+        }
+        print_dry_run_command(cli, &args);
+        return Ok(EXIT_SUCCESS);
+    }
+
+    prepare_working_tree(git, cli)?;
+    git.run_interactive(&args)?;
+    if !cli.create {
+        print_detached_hint(git, cli);
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+/// Fast-forward the branch that was just checked out to its upstream, for
+/// `--pull`. Pulling is skipped, with a note, when there's nothing to pull
+/// from or local changes could get in the way. The checkout has already
+/// happened, so a failed pull says so rather than failing the whole run.
+fn pull_after_checkout(git: &dyn GitRunner, cli: &Cli, branch: &str) -> i32 {
+    let note = |message: String| {
+        if !cli.quiet {
+            eprintln!("{} {}", "Note:".yellow().bold(), message);
+        }
+    };
+    let has_upstream = get_upstream(git, branch).is_some();
+    if let Some(reason) = pull_skip_reason(branch, has_upstream, working_tree_is_dirty(git)) {
+        note(format!("Not pulling, since {}", reason));
+        return EXIT_SUCCESS;
+    }
+
+    match git.run_interactive(&PULL_ARGS) {
+        Ok(()) => EXIT_SUCCESS,
+        Err(e) => {
+            eprintln!(
+                "Error: Checked out '{}', but pulling it failed: {}",
+                branch, e
+            );
+            exit_code(&e)
+        }
+    }
+}
+
+/// Why `--pull` skips pulling `branch`, if it does, going by whether it has
+/// an upstream and the working tree is dirty once it's checked out
+fn pull_skip_reason(branch: &str, has_upstream: bool, dirty: bool) -> Option<String> {
+    if !has_upstream {
+        Some(format!("'{}' has no upstream", branch))
+    } else if dirty {
+        Some("there are uncommitted changes".to_string())
+    } else {
+        None
+    }
+}
+
+/// Like `pull_after_checkout`, but only show whether the pull would run. A
+/// branch created to track a remote one will have an upstream, and
+/// `--force` or `--autostash` leave no changes behind.
+fn print_dry_run_pull(git: &dyn GitRunner, cli: &Cli, branch: &str, tracks_remote: bool) {
+    let has_upstream = tracks_remote || get_upstream(git, branch).is_some();
+    let dirty = !cli.force && !cli.autostash && working_tree_is_dirty(git);
+    match pull_skip_reason(branch, has_upstream, dirty) {
+        Some(reason) if cli.dry_run => println!("Would not pull, since {}", reason),
+        Some(_) => {}
+        None => print_dry_run_followup(cli, &PULL_ARGS),
+    }
+}
+
+/// How `--pull` updates the checked out branch, never creating a merge
+const PULL_ARGS: [&str; 2] = ["pull", "--ff-only"];
+
+/// Show a git command line as it would be typed, quoting any arguments
+/// that the shell would split or expand
+fn format_git_command(args: &[&str]) -> String {
+    let mut command = String::from("git");
+    for arg in args {
+        command.push(' ');
+        let plain = !arg.is_empty()
+            && arg
+                .chars()
+                .all(|c| c.is_alphanumeric() || "-_./=:@+,%^~".contains(c));
+        if plain {
+            command.push_str(arg);
+        } else {
+            command.push_str(&format!("'{}'", arg.replace('\'', r"'\''")));
+        }
+    }
+    command
+}
+
+/// With `--dry-run`, also show the git command that would be run. A bare
+/// repository only reports the match, since nothing can be checked out there.
+fn print_dry_run_command(cli: &Cli, args: &[&str]) {
+    if cli.dry_run {
+        println!("Would run: {}", format_git_command(args));
+    }
+}
+
+/// Like `print_dry_run_command`, for a git command that would be run after
+/// the first one
+fn print_dry_run_followup(cli: &Cli, args: &[&str]) {
+    if cli.dry_run {
+        println!("Would then run: {}", format_git_command(args));
+    }
+}
+
+/// Check out `branch` in a new worktree at `path`, which leaves the current
+/// checkout, and any changes in it, alone
+fn check_out_in_worktree(
+    git: &dyn GitRunner,
+    cli: &Cli,
+    path: &Path,
+    branch: &Branch,
+    dry_run: bool,
+) -> Result<i32, GitFuzzyError> {
+    let path_arg = path.to_string_lossy();
+    let remotes = get_git_remotes(git);
+    let args = add_worktree_args(&path_arg, branch, &remotes, &cli.git_args)?;
+    if dry_run {
+        println!(
+            "Would add a worktree at '{}' for {}",
+            path.display(),
+            describe_branch(branch)
+        );
+        print_dry_run_command(cli, &args);
+        return Ok(EXIT_SUCCESS);
+    }
+    git.run_interactive(&args)?;
+    Ok(EXIT_SUCCESS)
+}
+
+/// After checking out a commit, point out how to keep any work done there
+fn print_detached_hint(git: &dyn GitRunner, cli: &Cli) {
+    if cli.quiet {
+        return;
+    }
+    if let Some(sha) = get_head_commit(git) {
+        eprintln!(
+            "You are now in detached HEAD at {}; create a branch with git-fuzzy --create <name>",
+            sha
+        );
     }
 }
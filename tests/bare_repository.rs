@@ -0,0 +1,25 @@
+mod common;
+
+use common::{git, git_as_tester, git_fuzzy, scratch_dir};
+
+#[test]
+fn bare_repository_reports_match_without_checkout() {
+    let dir = scratch_dir("bare");
+
+    // Bare repositories have no working tree to commit from, so build the
+    // branches from an empty tree directly
+    git(&dir, &["init", "--quiet", "--bare"]);
+    let tree = git(&dir, &["hash-object", "-t", "tree", "-w", "--stdin"]);
+    let commit = git_as_tester(&dir, &["commit-tree", &tree, "-m", "initial commit"]);
+    git(&dir, &["update-ref", "refs/heads/main", &commit]);
+    git(&dir, &["update-ref", "refs/heads/feature/login", &commit]);
+
+    let output = git_fuzzy(&dir, &["login"]);
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        "Would check out local branch 'feature/login'"
+    );
+}
@@ -0,0 +1,57 @@
+//! Helpers shared by the integration tests, which build throwaway
+//! repositories and run the git-fuzzy binary against them
+
+// Each test file is its own crate and only uses some of these
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Run git in `dir`, panicking if it fails, and return its stdout
+pub fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .expect("git should run");
+    assert!(output.status.success(), "git {:?} failed", args);
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+/// Like `git`, with an identity to commit as, since the test machine may not
+/// have one configured
+pub fn git_as_tester(dir: &Path, args: &[&str]) -> String {
+    let mut with_identity = vec!["-c", "user.name=Test", "-c", "user.email=test@example.com"];
+    with_identity.extend_from_slice(args);
+    git(dir, &with_identity)
+}
+
+/// Create an empty directory for one test's repositories, unique to this test
+/// run. The test removes it once it's done.
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("git-fuzzy-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Create a repository in `dir` with a `main` branch and one empty commit
+pub fn init_with_commit(dir: &Path) {
+    std::fs::create_dir_all(dir).unwrap();
+    git(dir, &["init", "--quiet", "-b", "main"]);
+    git_as_tester(
+        dir,
+        &["commit", "--quiet", "--allow-empty", "-m", "initial commit"],
+    );
+}
+
+/// Run git-fuzzy in `dir`, like `git -C`
+pub fn git_fuzzy(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_git-fuzzy"))
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .expect("git-fuzzy should run")
+}
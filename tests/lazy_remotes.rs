@@ -0,0 +1,27 @@
+mod common;
+
+use common::{git, git_fuzzy, init_with_commit, scratch_dir};
+
+#[test]
+fn remote_branch_named_exactly_beats_local_typo_match() {
+    let root = scratch_dir("lazy");
+    let origin = root.join("origin");
+    let clone = root.join("clone");
+
+    init_with_commit(&origin);
+    git(&origin, &["branch", "feature-login"]);
+    git(&root, &["clone", "--quiet", "origin", "clone"]);
+
+    // The local branch is only within an edit of the pattern, while the
+    // remote-only branch is named by it exactly
+    git(&clone, &["branch", "feature-logon"]);
+
+    let output = git_fuzzy(&clone, &["--print", "feature-login"]);
+    let _ = std::fs::remove_dir_all(&root);
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        "origin/feature-login"
+    );
+}
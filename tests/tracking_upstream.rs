@@ -0,0 +1,56 @@
+mod common;
+
+use common::{git, git_fuzzy, init_with_commit, scratch_dir};
+use std::path::PathBuf;
+
+/// Create a repository with a clone of it, returning the clone. The clone's
+/// `origin` and `upstream` remotes both have a `feature/login` branch.
+fn clone_with_remote_branch(name: &str) -> (PathBuf, PathBuf) {
+    let root = scratch_dir(name);
+    let origin = root.join("origin");
+    let clone = root.join("clone");
+
+    init_with_commit(&origin);
+    git(&origin, &["branch", "feature/login"]);
+
+    git(&root, &["clone", "--quiet", "origin", "clone"]);
+    git(
+        &clone,
+        &["remote", "add", "upstream", origin.to_str().unwrap()],
+    );
+    git(&clone, &["fetch", "--quiet", "upstream"]);
+    (root, clone)
+}
+
+#[test]
+fn track_sets_upstream_to_matched_remote() {
+    let (root, clone) = clone_with_remote_branch("track");
+
+    let output = git_fuzzy(&clone, &["--track", "origin/feature/log"]);
+    let upstream = git(
+        &clone,
+        &["rev-parse", "--abbrev-ref", "feature/login@{upstream}"],
+    );
+    let _ = std::fs::remove_dir_all(&root);
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(upstream, "origin/feature/login");
+}
+
+#[test]
+fn set_upstream_to_overrides_matched_remote() {
+    let (root, clone) = clone_with_remote_branch("set-upstream");
+
+    let output = git_fuzzy(
+        &clone,
+        &["--set-upstream-to", "upstream", "origin/feature/log"],
+    );
+    let upstream = git(
+        &clone,
+        &["rev-parse", "--abbrev-ref", "feature/login@{upstream}"],
+    );
+    let _ = std::fs::remove_dir_all(&root);
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(upstream, "upstream/feature/login");
+}